@@ -0,0 +1,26 @@
+// Copyright © 2019 piet-dx12 developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Generate the HLSL readers for the scene types from their schema.
+
+use piet_gpu_codegen::{Schema, TargetLang};
+
+fn main() {
+    let mut schema = Schema::new();
+    for file in &["piet-gpu-types/src/common.rs", "piet-gpu-types/src/scene.rs"] {
+        if let Err(e) = schema.add_file(file) {
+            panic!("{}", e);
+        }
+    }
+    schema.rerun_if_changed();
+    let out_dir = std::env::var_os("OUT_DIR").expect("OUT_DIR is not set");
+    let readers_path = std::path::Path::new(&out_dir).join("readers.hlsl");
+    if let Err(e) = schema.write_shader("scene", TargetLang::Hlsl, readers_path) {
+        panic!("{}", e);
+    }
+}
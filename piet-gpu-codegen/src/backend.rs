@@ -0,0 +1,377 @@
+//! The syntax of the target shader languages.
+//!
+//! The default methods use C-like syntax, and load and store words of
+//! arrays declared by the module, as GLSL and WGSL can't pass buffers to
+//! functions. The backends override what differs.
+
+use std::fmt::Write;
+
+use crate::layout::size_in_uints;
+use crate::{GpuModule, GpuScalar, GpuTypeDef};
+
+pub(crate) trait Backend {
+    /// The name used for the "ref" argument of generated functions.
+    fn ref_name(&self) -> &'static str {
+        "ref"
+    }
+
+    /// The name used for the packed word argument of bit field extractors.
+    fn package_name(&self) -> &'static str {
+        "package"
+    }
+
+    /// A single typed function parameter.
+    fn param(&self, ty: &str, name: &str) -> String {
+        format!("{} {}", ty, name)
+    }
+
+    /// The typed function arguments for "buf" and "ref".
+    fn buf_and_ref_args(&self, ref_type: &str) -> String {
+        self.param(ref_type, self.ref_name())
+    }
+
+    /// The typed function arguments for a writable "buf" and "ref".
+    fn rw_buf_and_ref_args(&self, ref_type: &str) -> String {
+        self.param(ref_type, self.ref_name())
+    }
+
+    /// Whether buffers are passed to the generated functions, rather than
+    /// declared by the module.
+    fn passes_buffers(&self) -> bool {
+        false
+    }
+
+    /// The "buf" argument when calling a generated reader, if any.
+    fn buf_call_arg(&self) -> &'static str {
+        ""
+    }
+
+    /// The opening line of a function definition, up to and including the brace.
+    fn fn_header(&self, ret_type: &str, name: &str, params: &str) -> String {
+        format!("{} {}({}) {{\n", ret_type, name, params)
+    }
+
+    /// The header of a loop over `i` from 0 to `n`.
+    fn for_range_header(&self) -> &'static str {
+        "for (uint i = 0u; i < n; i++)"
+    }
+
+    /// A local variable declaration statement, with an optional initializer.
+    fn var_decl(&self, ty: &str, name: &str, init: Option<&str>) -> String {
+        let decl = format!("{} {}", ty, name);
+        match init {
+            Some(init) => format!("    {} = {};\n", decl, init),
+            None => format!("    {};\n", decl),
+        }
+    }
+
+    /// A member of a struct definition.
+    fn struct_field(&self, ty: &str, name: &str) -> String {
+        format!("    {} {};\n", ty, name)
+    }
+
+    /// A fixed-size array member of a struct definition.
+    fn struct_array_field(&self, ty: &str, name: &str, len: usize) -> String {
+        format!("    {} {}[{}];\n", ty, name, len)
+    }
+
+    /// A named integer constant.
+    fn define(&self, name: &str, value: usize) -> String {
+        format!("#define {} {}\n", name, value)
+    }
+
+    /// A named 32 bit unsigned constant, written in hex.
+    fn define_hex(&self, name: &str, value: u32) -> String {
+        format!("#define {} {:#010x}u\n", name, value)
+    }
+
+    /// Wrap `code` in an include guard. Reference aliases and `#define`s are
+    /// left outside, as repeating them is allowed.
+    fn include_guard(&self, guard: &str, code: &str) -> String {
+        format!("#ifndef {}\n#define {}\n{}\n#endif\n\n", guard, guard, code.trim_end())
+    }
+
+    /// Wrap the functions writing to the module's buffer.
+    fn writers(&self, _module: &GpuModule, code: &str) -> String {
+        code.into()
+    }
+
+    /// Declarations of the buffers the module reads and writes, if they
+    /// aren't passed to the functions.
+    fn buffer_decls(&self, _module: &GpuModule) -> String {
+        String::new()
+    }
+
+    /// An alias of `uint` for the reference type of a definition.
+    fn ref_alias(&self, name: &str) -> String {
+        format!("typedef uint {}Ref;\n", name)
+    }
+
+    /// An unsigned integer literal.
+    fn uint_literal(&self, value: usize) -> String {
+        value.to_string()
+    }
+
+    /// The offset stored for a `None` reference, matching `encoder::NULL_REF`.
+    fn null_ref(&self) -> &'static str {
+        "0xffffffffu"
+    }
+
+    /// Like `simplified_add`, but with a literal suitable for the target.
+    fn add_offset(&self, var_name: &str, c: usize) -> String {
+        if c == 0 {
+            String::from(var_name)
+        } else {
+            format!("{} + {}", var_name, self.uint_literal(c))
+        }
+    }
+
+    /// A uint expression converted from bytes to a word index.
+    fn word_index(&self, var_name: &str) -> String {
+        format!("{} >> {}", var_name, self.uint_literal(2))
+    }
+
+    /// A uint vector (or scalar, for size 1) with all components zero.
+    fn zero_uint(&self, size: usize) -> String {
+        let zero = self.uint_literal(0);
+        if size == 1 {
+            zero
+        } else {
+            let zeros = vec![zero; size];
+            format!("{}({})", self.vector_typename(GpuScalar::U32, size), zeros.join(", "))
+        }
+    }
+
+    /// Whether 8 and 16 bit values have types of their own, rather than
+    /// being unpacked to 32 bits.
+    fn has_small_scalars(&self) -> bool {
+        false
+    }
+
+    /// The name of a scalar type.
+    fn scalar_typename(&self, scalar: GpuScalar) -> &'static str {
+        match scalar {
+            GpuScalar::F32 => "float",
+            GpuScalar::I8 => "char",
+            GpuScalar::I16 => "short",
+            GpuScalar::I32 => "int",
+            GpuScalar::U8 => "uchar",
+            GpuScalar::U16 => "ushort",
+            GpuScalar::U32 => "uint",
+            GpuScalar::F16 => "half",
+            GpuScalar::Unorm8 => "uchar",
+            GpuScalar::Unorm16 => "ushort",
+            GpuScalar::Snorm8 => "char",
+            GpuScalar::Snorm16 => "short",
+            GpuScalar::Bool => "bool",
+            GpuScalar::UBits(..) | GpuScalar::IBits(..) => {
+                panic!("Internal logic error: bit fields are unpacked to 32 bit values")
+            }
+        }
+    }
+
+    /// The unsigned 32 bit type packed fields are stored as.
+    fn uint_typename(&self) -> &'static str {
+        self.scalar_typename(GpuScalar::U32)
+    }
+
+    /// The name of a vector type with the given scalar element type.
+    fn vector_typename(&self, scalar: GpuScalar, size: usize) -> String {
+        let base = self.scalar_typename(scalar);
+        if size == 1 {
+            return base.into();
+        }
+        format!("{}{}", base, size)
+    }
+
+    /// The name of a vector type as stored in a packed struct.
+    fn packed_vector_typename(&self, typename: String) -> String {
+        typename
+    }
+
+    /// Convert a uint vector holding the bits of the given scalar into a
+    /// vector of it; normalized values, bools and bit fields are converted
+    /// by `GpuScalar::cvt_vec`.
+    fn bits_to_value(&self, scalar: GpuScalar, inner: &str, size: usize) -> String;
+
+    /// Convert a vector of the given scalar into uint bits, the inverse of
+    /// `bits_to_value`. Values that are already uints are handled by
+    /// `GpuScalar::cvt_to_uint_vec`.
+    fn value_to_bits(&self, scalar: GpuScalar, inner: &str, size: usize) -> String;
+
+    /// The 2x3 matrix type of an `Affine2`, applied to `float3(x, y, 1)`.
+    fn affine_typename(&self) -> &'static str;
+
+    /// An `Affine2` from its three columns.
+    fn affine_from_columns(&self, columns: &str) -> String {
+        format!("{}({})", self.affine_typename(), columns)
+    }
+
+    /// An expression indexable by column for the `Affine2` in `value`.
+    fn affine_columns(&self, value: &str) -> String {
+        value.to_string()
+    }
+
+    /// An expression for loading an `Affine2`, whose columns are stored in order.
+    fn affine_load_expr(&self, module: &GpuModule, offset: usize) -> String {
+        let columns = (0..3)
+            .map(|i| {
+                self.bits_to_value(GpuScalar::F32, &self.load_expr(module, offset + 8 * i, 2), 2)
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        self.affine_from_columns(&columns)
+    }
+
+    /// Statements storing an `Affine2`, the inverse of `affine_load_expr`.
+    fn affine_store_stmts(&self, module: &GpuModule, offset: usize, value: &str) -> String {
+        let columns = self.affine_columns(value);
+        (0..3)
+            .map(|i| {
+                let column = format!("{}[{}]", columns, i);
+                self.store_stmt(
+                    module,
+                    offset + 8 * i,
+                    2,
+                    &self.value_to_bits(GpuScalar::F32, &column, 2),
+                )
+            })
+            .collect()
+    }
+
+    /// An expression for loading a number of uints.
+    fn load_expr(&self, module: &GpuModule, offset: usize, size: usize) -> String {
+        self.load_expr_at(module, self.ref_name(), offset, size)
+    }
+
+    /// An expression for loading a number of uints relative to the byte offset
+    /// in the variable `ref_name`.
+    ///
+    /// Offsets into word arrays are always 4 byte aligned.
+    fn load_expr_at(
+        &self,
+        module: &GpuModule,
+        ref_name: &str,
+        offset: usize,
+        size: usize,
+    ) -> String {
+        let buf_name = module.buf_name();
+        let ix = self.word_index(ref_name);
+        let words = (0..size)
+            .map(|i| {
+                if offset == 0 && i == 0 {
+                    format!("{}[{}]", buf_name, ix)
+                } else {
+                    format!(
+                        "{}[{}]",
+                        buf_name,
+                        self.add_offset(&format!("({})", ix), offset / 4 + i)
+                    )
+                }
+            })
+            .collect::<Vec<String>>();
+        if size == 1 {
+            words[0].clone()
+        } else {
+            format!(
+                "{}({})",
+                self.vector_typename(GpuScalar::U32, size),
+                words.join(", ")
+            )
+        }
+    }
+
+    /// An expression loading a uint from the buffer being written, for stores
+    /// that keep some of its bits.
+    fn rw_load_expr(&self, module: &GpuModule, offset: usize) -> String {
+        let ix = self.word_index(self.ref_name());
+        let word_ix = if offset == 0 {
+            ix
+        } else {
+            self.add_offset(&format!("({})", ix), offset / 4)
+        };
+        format!("{}[{}]", module.dst_buf_name(), word_ix)
+    }
+
+    /// A statement storing a uint expression (a vector for size > 1).
+    fn store_stmt(&self, module: &GpuModule, offset: usize, size: usize, value: &str) -> String {
+        self.store_stmt_at(module, self.ref_name(), offset, size, value)
+    }
+
+    /// A statement storing a uint expression relative to the byte offset in
+    /// the variable `ref_name`.
+    fn store_stmt_at(
+        &self,
+        module: &GpuModule,
+        ref_name: &str,
+        offset: usize,
+        size: usize,
+        value: &str,
+    ) -> String {
+        let buf_name = module.dst_buf_name();
+        let ix = self.word_index(ref_name);
+        let mut r = String::new();
+        for i in 0..size {
+            let word_ix = if offset == 0 && i == 0 {
+                ix.clone()
+            } else {
+                self.add_offset(&format!("({})", ix), offset / 4 + i)
+            };
+            let component = if size == 1 {
+                value.to_string()
+            } else {
+                format!("{}[{}]", value, i)
+            };
+            writeln!(r, "    {}[{}] = {};", buf_name, word_ix, component).unwrap();
+        }
+        r
+    }
+
+    /// The parameters of the `_copy` functions, and the arguments passing them
+    /// on, with `{}` for the offsets.
+    fn copy_params(&self, _rw: bool) -> (String, &'static str) {
+        let uint = self.uint_typename();
+        (
+            format!("{}, {}", self.param(uint, "src_ref"), self.param(uint, "dst_ref")),
+            "{}, {}",
+        )
+    }
+
+    /// The body of a `_copy` function moving `size` bytes.
+    fn copy_body(&self, module: &GpuModule, size: usize, rw: bool) -> String {
+        let uint = self.uint_typename();
+        let src_buf = if rw { module.dst_buf_name() } else { module.buf_name() };
+        let dst_buf = module.dst_buf_name();
+        let src_ix = self.word_index("src_ref");
+        let dst_ix = self.word_index("dst_ref");
+        let mut r = String::new();
+        write!(r, "{}", self.var_decl(uint, "src_ix", Some(&src_ix))).unwrap();
+        write!(r, "{}", self.var_decl(uint, "dst_ix", Some(&dst_ix))).unwrap();
+        for i in 0..size_in_uints(size) {
+            writeln!(
+                r,
+                "    {}[{}] = {}[{}];",
+                dst_buf,
+                self.add_offset("dst_ix", i),
+                src_buf,
+                self.add_offset("src_ix", i),
+            )
+                .unwrap();
+        }
+        r
+    }
+
+    /// Code for the definition that only this target has.
+    fn def_extras(&self, _def: &GpuTypeDef) -> String {
+        String::new()
+    }
+}
+
+/// The ` + offset` added to a byte offset, if any.
+pub(crate) fn offset_tail(offset: usize) -> String {
+    if offset == 0 {
+        "".into()
+    } else {
+        format!(" + {}", offset)
+    }
+}
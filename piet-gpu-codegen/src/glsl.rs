@@ -0,0 +1,99 @@
+//! GLSL compute shaders, reading from buffer blocks declared by the module.
+
+use std::fmt::Write;
+
+use crate::backend::Backend;
+use crate::{to_camel_case, GpuModule, GpuScalar};
+
+pub(crate) struct Glsl;
+
+impl Glsl {
+    /// The macro holding the binding of the module's writable buffer.
+    fn dst_binding_name(module: &GpuModule) -> String {
+        format!("{}_BINDING", module.dst_buf_name().to_uppercase())
+    }
+}
+
+impl Backend for Glsl {
+    /// GLSL only declares the writable buffer block when the kernel gives it
+    /// a binding, so the writers are left out otherwise.
+    fn writers(&self, module: &GpuModule, code: &str) -> String {
+        format!(
+            "#ifdef {}\n{}\n#endif\n\n",
+            Glsl::dst_binding_name(module),
+            code.trim_end()
+        )
+    }
+
+    /// The read-only binding defaults to 0, and can be overridden by defining
+    /// `<MODULE>_BUF_BINDING` before the generated code is included. The
+    /// writable block, and the functions writing to it, are only declared
+    /// when `<MODULE>_DST_BUF_BINDING` is defined.
+    fn buffer_decls(&self, module: &GpuModule) -> String {
+        let mut r = String::new();
+        let buf_name = module.buf_name();
+        let binding = format!("{}_BINDING", buf_name.to_uppercase());
+        write!(r, "#ifndef {}\n#define {} 0\n#endif\n", binding, binding).unwrap();
+        write!(
+            r,
+            "layout(std430, binding = {}) readonly buffer {} {{\n    uint {}[];\n}};\n\n",
+            binding,
+            to_camel_case(&buf_name),
+            buf_name,
+        )
+            .unwrap();
+        let dst_buf_name = module.dst_buf_name();
+        let dst_binding = Glsl::dst_binding_name(module);
+        write!(
+            r,
+            "#ifdef {}\nlayout(std430, binding = {}) buffer {} {{\n    uint {}[];\n}};\n#endif\n\n",
+            dst_binding,
+            dst_binding,
+            to_camel_case(&dst_buf_name),
+            dst_buf_name,
+        )
+            .unwrap();
+        r
+    }
+
+    fn ref_alias(&self, name: &str) -> String {
+        format!("#define {}Ref uint\n", name)
+    }
+
+    fn vector_typename(&self, scalar: GpuScalar, size: usize) -> String {
+        if size == 1 {
+            return self.scalar_typename(scalar).into();
+        }
+        let prefix = match scalar {
+            GpuScalar::F32 => "",
+            GpuScalar::I32 => "i",
+            GpuScalar::U32 => "u",
+            _ => panic!("no GLSL vector type for {}", scalar),
+        };
+        format!("{}vec{}", prefix, size)
+    }
+
+    fn bits_to_value(&self, scalar: GpuScalar, inner: &str, size: usize) -> String {
+        match scalar {
+            GpuScalar::F16 => format!("unpackHalf2x16({}).x", inner),
+            GpuScalar::F32 => format!("uintBitsToFloat({})", inner),
+            GpuScalar::I32 => format!("{}({})", self.vector_typename(GpuScalar::I32, size), inner),
+            // Small signed values are sign-extended to int by their extractor.
+            _ => inner.into(),
+        }
+    }
+
+    fn value_to_bits(&self, scalar: GpuScalar, inner: &str, size: usize) -> String {
+        match scalar {
+            GpuScalar::F32 => format!("floatBitsToUint({})", inner),
+            GpuScalar::F16 => format!("packHalf2x16(vec2({}, 0.0))", inner),
+            // Small unsigned values are already unpacked to uint.
+            GpuScalar::U8 | GpuScalar::U16 => inner.into(),
+            _ => format!("{}({})", self.vector_typename(GpuScalar::U32, size), inner),
+        }
+    }
+
+    fn affine_typename(&self) -> &'static str {
+        "mat3x2"
+    }
+}
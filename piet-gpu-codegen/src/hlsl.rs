@@ -0,0 +1,149 @@
+//! HLSL, reading from `ByteAddressBuffer`s passed to the functions.
+
+use std::fmt::Write;
+
+use crate::backend::{offset_tail, Backend};
+use crate::shader::{simplified_add, vector_size_str};
+use crate::{GpuModule, GpuScalar};
+
+pub(crate) struct Hlsl;
+
+impl Backend for Hlsl {
+    fn buf_and_ref_args(&self, ref_type: &str) -> String {
+        format!("ByteAddressBuffer buf, {}", self.param(ref_type, self.ref_name()))
+    }
+
+    fn rw_buf_and_ref_args(&self, ref_type: &str) -> String {
+        format!("RWByteAddressBuffer buf, {}", self.param(ref_type, self.ref_name()))
+    }
+
+    fn passes_buffers(&self) -> bool {
+        true
+    }
+
+    fn buf_call_arg(&self) -> &'static str {
+        "buf, "
+    }
+
+    fn fn_header(&self, ret_type: &str, name: &str, params: &str) -> String {
+        format!("inline {} {}({}) {{\n", ret_type, name, params)
+    }
+
+    fn bits_to_value(&self, scalar: GpuScalar, inner: &str, _size: usize) -> String {
+        match scalar {
+            GpuScalar::F32 => format!("asfloat({})", inner),
+            GpuScalar::I32 => format!("asint({})", inner),
+            GpuScalar::F16 => format!("f16tof32({})", inner),
+            // Small signed values are sign-extended to int by their extractor.
+            _ => inner.into(),
+        }
+    }
+
+    fn value_to_bits(&self, scalar: GpuScalar, inner: &str, size: usize) -> String {
+        match scalar {
+            GpuScalar::F32 | GpuScalar::I32 => format!("asuint({})", inner),
+            GpuScalar::F16 => format!("f32tof16({})", inner),
+            // Small unsigned values are already unpacked to uint.
+            GpuScalar::U8 | GpuScalar::U16 => inner.into(),
+            _ => format!("{}({})", self.vector_typename(GpuScalar::U32, size), inner),
+        }
+    }
+
+    /// HLSL counts rows first, the other targets count columns first.
+    fn affine_typename(&self) -> &'static str {
+        "float2x3"
+    }
+
+    fn affine_from_columns(&self, columns: &str) -> String {
+        format!("transpose(float3x2({}))", columns)
+    }
+
+    fn affine_columns(&self, value: &str) -> String {
+        format!("transpose({})", value)
+    }
+
+    fn load_expr_at(
+        &self,
+        _module: &GpuModule,
+        ref_name: &str,
+        offset: usize,
+        size: usize,
+    ) -> String {
+        format!("buf.Load{}({}{})", vector_size_str(size), ref_name, offset_tail(offset))
+    }
+
+    fn rw_load_expr(&self, module: &GpuModule, offset: usize) -> String {
+        self.load_expr(module, offset, 1)
+    }
+
+    fn store_stmt_at(
+        &self,
+        _module: &GpuModule,
+        ref_name: &str,
+        offset: usize,
+        size: usize,
+        value: &str,
+    ) -> String {
+        format!(
+            "    buf.Store{}({}{}, {});\n",
+            vector_size_str(size),
+            ref_name,
+            offset_tail(offset),
+            value
+        )
+    }
+
+    fn copy_params(&self, rw: bool) -> (String, &'static str) {
+        (
+            format!(
+                "{} src, uint src_ref, RWByteAddressBuffer dst, uint dst_ref",
+                if rw { "RWByteAddressBuffer" } else { "ByteAddressBuffer" }
+            ),
+            "src, {}, dst, {}",
+        )
+    }
+
+    fn copy_body(&self, _module: &GpuModule, size: usize, _rw: bool) -> String {
+        let mut r = String::new();
+        let quotient_in_u32x4 = size / (4 * GpuScalar::U32.size());
+        let remainder_in_u32s = (size / 4) % 4;
+        for i in 0..quotient_in_u32x4 {
+            writeln!(
+                r,
+                "    uint4 group{} = src.Load4({});",
+                i,
+                simplified_add("src_ref", i * 4 * 4)
+            )
+                .unwrap();
+            writeln!(
+                r,
+                "    dst.Store4({}, group{});",
+                simplified_add("dst_ref", i * 4 * 4),
+                i,
+            )
+                .unwrap();
+        }
+        if remainder_in_u32s > 0 {
+            let tail = vector_size_str(remainder_in_u32s);
+            writeln!(
+                r,
+                "{}    uint{} group{} = src.Load{}({});",
+                if quotient_in_u32x4 > 0 { "\n" } else { "" },
+                tail,
+                quotient_in_u32x4,
+                tail,
+                simplified_add("src_ref", quotient_in_u32x4 * 4 * 4)
+            )
+                .unwrap();
+            writeln!(
+                r,
+                "    dst.Store{}({}, group{});",
+                tail,
+                simplified_add("dst_ref", quotient_in_u32x4 * 4 * 4),
+                quotient_in_u32x4
+            )
+                .unwrap();
+        }
+        r
+    }
+}
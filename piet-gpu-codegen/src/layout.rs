@@ -0,0 +1,481 @@
+//! The layout of the types in the encoded buffers.
+
+use std::fmt::Write;
+
+use crate::{GpuModule, GpuScalar, GpuType, GpuTypeDef, LayoutMode};
+
+/// Return number of `uints` required to store `num_bytes` bytes.
+pub(crate) fn size_in_uints(num_bytes: usize) -> usize {
+    // a `uint` has a size of 4 bytes, (size_in_bytes + 4 - 1) / 4
+    num_bytes.div_ceil(4)
+}
+
+/// Round `offset` up to a multiple of `align`, which is a power of two.
+pub(crate) fn align_up(offset: usize, align: usize) -> usize {
+    (offset + align - 1) & !(align - 1)
+}
+
+/// A `PackedField` stores `StoredField`s
+#[derive(Clone)]
+pub(crate) struct StoredField {
+    pub(crate) name: String,
+    pub(crate) ty: GpuType,
+    /// The offset of the field within the packed field, in bits.
+    pub(crate) offset: usize,
+}
+
+/// A `PackedStruct` has `PackedField`s
+#[derive(Clone)]
+pub(crate) struct PackedField {
+    pub(crate) name: String,
+    /// The type of the package, as stored packed.
+    pub(crate) ty: Option<GpuType>,
+    pub(crate) stored_fields: Vec<StoredField>,
+    /// Bits used so far by the stored fields.
+    pub(crate) bits: usize,
+    /// Alignment of the packed field in bytes.
+    pub(crate) align: usize,
+    /// The number of low bits holding the narrow tag of an enum variant.
+    pub(crate) tag_bits: usize,
+}
+
+/// Possible results of the `pack` method on a `PackedField`.
+#[derive(PartialEq)]
+pub(crate) enum PackResult {
+    SuccessAndOpen,
+    SuccessAndClosed,
+    FailAndClosed,
+}
+
+#[derive(Clone)]
+pub(crate) struct PackedStruct {
+    pub(crate) name: String,
+    pub(crate) packed_fields: Vec<PackedField>,
+    pub(crate) is_enum_variant: bool,
+    /// The tag of the enum variant holding the struct, if there's only one.
+    pub(crate) tag: Option<u32>,
+    /// Alignment of the struct in bytes; its size is a multiple of this.
+    pub(crate) align: usize,
+}
+
+impl PackedField {
+    pub(crate) fn new() -> PackedField {
+        PackedField {
+            name: String::new(),
+            ty: None,
+            bits: 0,
+            stored_fields: vec![],
+            align: 4,
+            tag_bits: 0,
+        }
+    }
+
+    /// An empty packed field starting after a tag of `tag_size` bytes.
+    pub(crate) fn with_tag(tag_size: usize) -> PackedField {
+        PackedField {
+            bits: 8 * tag_size,
+            tag_bits: 8 * tag_size,
+            ..PackedField::new()
+        }
+    }
+
+    pub(crate) fn pack(
+        &mut self,
+        module: &GpuModule,
+        field_type: &GpuType,
+        field_name: &str,
+    ) -> Result<PackResult, String> {
+        if !self.is_closed() {
+            let field_bits = field_type.bits(module);
+            // Bit fields may start at any bit, other fields start at a byte.
+            let start = if field_type.is_bitfield() {
+                self.bits
+            } else {
+                align_up(self.bits, 8)
+            };
+
+            if start + field_bits > 32 {
+                if self.is_empty() {
+                    self.stored_fields.push(StoredField {
+                        name: field_name.into(),
+                        ty: field_type.clone(),
+                        offset: 0,
+                    });
+                    self.close(module).unwrap();
+                    Ok(PackResult::SuccessAndClosed)
+                } else {
+                    self.close(module).unwrap();
+                    Ok(PackResult::FailAndClosed)
+                }
+            } else {
+                self.stored_fields.push(StoredField {
+                    name: String::from(field_name),
+                    ty: field_type.clone(),
+                    offset: start,
+                });
+                self.bits = start + field_bits;
+                Ok(PackResult::SuccessAndOpen)
+            }
+        } else {
+            Err("cannot extend closed package".into())
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.stored_fields.is_empty()
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.ty.is_some()
+    }
+
+    /// True when the packed and unpacked types differ.
+    pub(crate) fn is_packed(&self, struct_result: bool) -> bool {
+        if self.tag_bits > 0 || self.stored_fields.len() != 1 {
+            return true;
+        }
+        match self.stored_fields[0].ty {
+            GpuType::Scalar(scalar) => scalar.size() < 4 || scalar.is_bitfield(),
+            GpuType::Vector(scalar, _) => scalar.size() < 4,
+            GpuType::InlineStruct(_) => struct_result,
+            _ => false,
+        }
+    }
+
+    pub(crate) fn close(&mut self, module: &GpuModule) -> Result<(), String> {
+        if !self.is_closed() {
+            if self.is_empty() {
+                Err("cannot close empty package".into())
+            } else {
+                let stored_field_names = self
+                    .stored_fields
+                    .iter()
+                    .map(|pf| pf.name.clone())
+                    .collect::<Vec<String>>();
+                self.name = stored_field_names.join("_");
+
+                if self.is_packed(false) {
+                    let end = self
+                        .stored_fields
+                        .iter()
+                        .map(|sf| sf.offset + sf.ty.bits(module))
+                        .max()
+                        .unwrap();
+                    let size_in_uints = size_in_uints(end.div_ceil(8));
+                    if size_in_uints == 1 {
+                        self.ty = Some(GpuType::Scalar(GpuScalar::U32));
+                    } else {
+                        self.ty = Some(GpuType::Vector(GpuScalar::U32, size_in_uints));
+                    }
+                } else {
+                    self.ty = Some(self.stored_fields[0].ty.clone());
+                }
+                Ok(())
+            }
+        } else {
+            Err("cannot close closed package".into())
+        }
+    }
+
+    pub(crate) fn size(&self, module: &GpuModule) -> Result<usize, String> {
+        if let Some(ty) = &self.ty {
+            Ok(ty.size(module))
+        } else {
+            Err("cannot calculate size of open packed field".into())
+        }
+    }
+}
+
+impl PackedStruct {
+    pub(crate) fn new(module: &GpuModule, name: &str, fields: &Vec<(String, GpuType)>) -> PackedStruct {
+        let attrs = &module.type_attrs[name];
+        let mut packed_fields: Vec<PackedField> = Vec::new();
+
+        // A narrow tag shares its word with the first fields, if they fit.
+        let tag_size = module.enum_variants.get(name).copied().unwrap_or(4);
+        let mut current_packed_field = if tag_size < 4 {
+            PackedField::with_tag(tag_size)
+        } else {
+            PackedField::new()
+        };
+        for (field_name, ty) in fields {
+            let field_attrs = attrs.field(field_name);
+            let align = ty
+                .alignment(module, attrs.mode)
+                .max(field_attrs.align.unwrap_or(4));
+            // Otherwise the tag keeps a word of its own.
+            let fits_with_tag = align <= 4
+                && attrs.pack
+                && field_attrs.pack
+                && current_packed_field.bits + ty.bits(module) <= 32;
+            if current_packed_field.tag_bits > 0 && current_packed_field.is_empty() && !fits_with_tag {
+                current_packed_field = PackedField::new();
+            }
+            // Aligned and unpacked fields get a packed field of their own.
+            if align > 4 || !(attrs.pack && field_attrs.pack) {
+                if !current_packed_field.is_empty() {
+                    current_packed_field.close(module).unwrap();
+                    packed_fields.push(current_packed_field);
+                }
+                current_packed_field = PackedField::new();
+                current_packed_field.align = align;
+                current_packed_field.pack(module, ty, field_name).unwrap();
+                if !current_packed_field.is_closed() {
+                    current_packed_field.close(module).unwrap();
+                }
+                packed_fields.push(current_packed_field);
+                current_packed_field = PackedField::new();
+                continue;
+            }
+            match current_packed_field.pack(module, ty, field_name).unwrap() {
+                PackResult::SuccessAndClosed => {
+                    packed_fields.push(current_packed_field);
+                    current_packed_field = PackedField::new();
+                }
+                PackResult::FailAndClosed => {
+                    packed_fields.push(current_packed_field);
+                    current_packed_field = PackedField::new();
+                    let res = current_packed_field.pack(module, ty, field_name).unwrap();
+                    if res == PackResult::SuccessAndClosed {
+                        packed_fields.push(current_packed_field);
+                        current_packed_field = PackedField::new();
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if !current_packed_field.is_closed() && !current_packed_field.is_empty() {
+            current_packed_field.close(module).unwrap();
+            packed_fields.push(current_packed_field);
+        }
+
+        let align = packed_fields
+            .iter()
+            .map(|packed_field| packed_field.align)
+            .fold(attrs.align.unwrap_or(4), usize::max);
+        PackedStruct {
+            name: format!("{}Packed", name),
+            packed_fields,
+            is_enum_variant: module.enum_variants.contains_key(name),
+            tag: module.variant_tag(name),
+            align,
+        }
+    }
+
+    /// Byte offsets of the packed fields.
+    ///
+    /// This is the one source of truth for the layout; the shader code, the
+    /// Rust encoder and the layout report are all derived from it.
+    pub(crate) fn packed_field_offsets(&self, module: &GpuModule) -> Vec<usize> {
+        // account for tag
+        let mut offset = if self.has_tag_word() { 4 } else { 0 };
+        self.packed_fields
+            .iter()
+            .map(|packed_field| {
+                let packed_field_offset = align_up(offset, packed_field.align);
+                offset = packed_field_offset + packed_field.size(module).unwrap();
+                packed_field_offset
+            })
+            .collect()
+    }
+
+    /// Bit offsets of the fields as declared in the schema.
+    pub(crate) fn field_bit_offsets(&self, module: &GpuModule) -> Vec<(String, usize)> {
+        let mut offsets = Vec::new();
+        for (packed_field, offset) in self
+            .packed_fields
+            .iter()
+            .zip(self.packed_field_offsets(module))
+        {
+            for sf in &packed_field.stored_fields {
+                offsets.push((sf.name.clone(), offset * 8 + sf.offset));
+            }
+        }
+        offsets
+    }
+
+    /// Whether the struct starts with a word holding only the tag, which is
+    /// the case for enum variants unless a narrow tag shares its word.
+    pub(crate) fn has_tag_word(&self) -> bool {
+        match self.packed_fields.first() {
+            Some(packed_field) if packed_field.tag_bits > 0 => false,
+            _ => self.is_enum_variant,
+        }
+    }
+
+    /// Size in bytes, including the tag of enum variants and any padding.
+    pub(crate) fn size(&self, module: &GpuModule) -> usize {
+        let end = match (self.packed_fields.last(), self.packed_field_offsets(module).last()) {
+            (Some(packed_field), Some(offset)) => offset + packed_field.size(module).unwrap(),
+            _ => {
+                if self.is_enum_variant {
+                    4
+                } else {
+                    0
+                }
+            }
+        };
+        align_up(end, self.align)
+    }
+}
+
+impl GpuType {
+    pub(crate) fn size(&self, module: &GpuModule) -> usize {
+        match self {
+            GpuType::Scalar(scalar) => scalar.size(),
+            GpuType::Vector(scalar, size) => scalar.size() * size,
+            GpuType::InlineStruct(name) => module.resolve_by_name(name).unwrap().size(module),
+            GpuType::Ref(_name) | GpuType::NullableRef(_name) => 4,
+            GpuType::Slice(_) => 8,
+            GpuType::Array(elem, len) => elem.size(module) * len,
+            GpuType::Affine2 => 24,
+        }
+    }
+
+    /// Size in bits, which is only less than `8 * size()` for bit fields.
+    pub(crate) fn bits(&self, module: &GpuModule) -> usize {
+        match self {
+            GpuType::Scalar(scalar) => scalar.bits(),
+            _ => 8 * self.size(module),
+        }
+    }
+
+    pub(crate) fn is_bitfield(&self) -> bool {
+        match self {
+            GpuType::Scalar(scalar) => scalar.is_bitfield(),
+            _ => false,
+        }
+    }
+
+    /// Alignment in bytes within a struct using the layout `mode`.
+    pub(crate) fn alignment(&self, module: &GpuModule, mode: LayoutMode) -> usize {
+        match self {
+            GpuType::InlineStruct(name) => module.resolve_by_name(name).unwrap().alignment(module),
+            GpuType::Array(elem, _) => elem.alignment(module, mode),
+            _ if mode == LayoutMode::Scalar => 4,
+            // The columns are loaded as uint2s, so it's aligned like them.
+            GpuType::Affine2 => 8,
+            // Small vectors are packed into a uint.
+            GpuType::Vector(scalar, size) => match size_in_uints(scalar.size() * size) {
+                1 => 4,
+                2 => 8,
+                _ => 16,
+            },
+            GpuType::Slice(_) => 8,
+            GpuType::Scalar(_) | GpuType::Ref(_) | GpuType::NullableRef(_) => 4,
+        }
+    }
+
+    /// Report whether type is a scalar or simple vector
+    pub(crate) fn is_small(&self) -> bool {
+        match self {
+            GpuType::Scalar(_) => true,
+            GpuType::Vector(_, _) => true,
+            GpuType::InlineStruct(_) => false,
+            GpuType::Ref(_) => true,
+            GpuType::NullableRef(_) => true,
+            GpuType::Slice(_) => true,
+            GpuType::Array(..) => false,
+            GpuType::Affine2 => false,
+        }
+    }
+}
+
+impl GpuTypeDef {
+    /// Size of the body of the definition.
+    pub(crate) fn size(&self, module: &GpuModule) -> usize {
+        match self {
+            GpuTypeDef::Struct(name, fields) => PackedStruct::new(module, name, fields).size(module),
+            GpuTypeDef::Enum(en) => {
+                let mut max_offset = 4;
+                for variant in &en.variants {
+                    // Variant structs include the tag.
+                    if let Some(name) = variant.struct_name(&en.name) {
+                        let size = module.resolve_by_name(&name).unwrap().size(module);
+                        max_offset = max_offset.max(size);
+                    }
+                }
+                align_up(max_offset, self.alignment(module))
+            }
+        }
+    }
+
+    /// Alignment of the body of the definition.
+    pub(crate) fn alignment(&self, module: &GpuModule) -> usize {
+        match self {
+            GpuTypeDef::Struct(name, fields) => PackedStruct::new(module, name, fields).align,
+            GpuTypeDef::Enum(en) => {
+                let mut align = module.type_attrs[&en.name].align.unwrap_or(4);
+                for variant in &en.variants {
+                    if let Some(name) = variant.struct_name(&en.name) {
+                        let variant_align = module.resolve_by_name(&name).unwrap().alignment(module);
+                        align = align.max(variant_align);
+                    }
+                }
+                align
+            }
+        }
+    }
+}
+
+impl GpuModule {
+    /// A hash of the resolved layout of the module, which changes whenever
+    /// the encoder and the shader code would no longer agree.
+    ///
+    /// This is FNV-1a over a description of every type, so it is stable
+    /// across compilers and platforms.
+    pub(crate) fn schema_hash(&self) -> u64 {
+        let mut desc = String::new();
+        for def in &self.defs {
+            write!(desc, "{} {} {};", def.name(), def.size(self), def.alignment(self)).unwrap();
+            match def {
+                GpuTypeDef::Struct(name, fields) => {
+                    let packed_form = PackedStruct::new(self, name, fields);
+                    write!(desc, "tag {};", packed_form.is_enum_variant).unwrap();
+                    for (packed_field, offset) in packed_form
+                        .packed_fields
+                        .iter()
+                        .zip(packed_form.packed_field_offsets(self))
+                    {
+                        write!(
+                            desc,
+                            "{} {} {};",
+                            packed_field.name,
+                            offset,
+                            packed_field.size(self).unwrap()
+                        )
+                            .unwrap();
+                        for sf in &packed_field.stored_fields {
+                            write!(
+                                desc,
+                                "{} {} {} {};",
+                                sf.name,
+                                sf.ty.schema_name(),
+                                sf.offset,
+                                sf.ty.bits(self)
+                            )
+                                .unwrap();
+                        }
+                    }
+                }
+                GpuTypeDef::Enum(en) => {
+                    write!(desc, "tag_size {};", en.tag_size).unwrap();
+                    for variant in &en.variants {
+                        write!(
+                            desc,
+                            "{} {} {:?};",
+                            variant.name,
+                            variant.tag,
+                            variant.struct_name(&en.name)
+                        )
+                            .unwrap();
+                    }
+                }
+            }
+        }
+        desc.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+}
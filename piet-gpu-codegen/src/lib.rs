@@ -0,0 +1,1239 @@
+//! Code generation for piet-gpu's GPU data structures.
+//!
+//! This is used by the proc macros of `piet-gpu-derive`, and by build scripts
+//! through [`Schema`].
+//!
+//! TODO: explain in detail how this works.
+//!
+//! A few notes that will be helpful. Structs are encoded differently depending
+//! on whether they appear as a variant in an enum; if so, the tag is included.
+//! This allows the alignment of the struct to take the tag into account.
+//! Narrow tags, from `#[tag(u8)]` or `#[tag(u16)]` on the enum, share their
+//! word with the first fields of the struct.
+//!
+//! The tag is part of the packed struct of a variant, as it is of the encoding:
+//! `_read` loads it, `_pack` fills it in and `_write` stores it, and a word
+//! shared with a narrow tag holds the tag in its low bits wherever it's packed.
+//! A struct held by several variants can't know its tag, so its packers take
+//! it as a `tag` parameter. `<Enum>_write_tag` changes only the tag.
+
+#[macro_use]
+extern crate quote;
+
+mod backend;
+mod glsl;
+mod hlsl;
+mod layout;
+mod msl;
+mod parse;
+mod resolve;
+mod rust;
+mod schema;
+mod shader;
+mod wgsl;
+
+use std::collections::{HashMap, HashSet};
+
+use crate::backend::Backend;
+use crate::resolve::{derived_module, Resolver};
+
+pub use crate::schema::{write_if_changed, Schema};
+
+/// The target shader language.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TargetLang {
+    Hlsl,
+    Msl,
+    Glsl,
+    Wgsl,
+}
+
+impl TargetLang {
+    /// The syntax of the language.
+    fn backend(self) -> &'static dyn Backend {
+        match self {
+            TargetLang::Hlsl => &hlsl::Hlsl,
+            TargetLang::Msl => &msl::Msl,
+            TargetLang::Glsl => &glsl::Glsl,
+            TargetLang::Wgsl => &wgsl::Wgsl,
+        }
+    }
+}
+
+/// A scalar that can be represented in a packed data structure.
+#[derive(Clone, Copy, PartialEq)]
+enum GpuScalar {
+    I8,
+    I16,
+    I32,
+    F32,
+    U8,
+    U16,
+    U32,
+    /// Half-precision float, stored as 16 bits and unpacked to `float`
+    /// (`half` in MSL). The Rust type is `encoder::F16`, which is
+    /// `half::f16` with the `half` feature of piet-gpu-types.
+    F16,
+    /// Unsigned normalized values from `#[unorm]`, unpacked to a `float` in
+    /// [0, 1].
+    Unorm8,
+    Unorm16,
+    /// Signed normalized values from `#[snorm]`, unpacked to a `float` in
+    /// [-1, 1].
+    Snorm8,
+    Snorm16,
+    /// A single bit, unpacked to `bool`.
+    Bool,
+    /// Unsigned and signed bit fields of the given width, from `uN` or
+    /// `#[bits(N)]`, unpacked to `uint` and `int`. The second value is the
+    /// size in bytes of the Rust integer holding the field.
+    UBits(u8, u8),
+    IBits(u8, u8),
+}
+
+/// An algebraic datatype.
+#[derive(Clone)]
+enum GpuType {
+    Scalar(GpuScalar),
+    Vector(GpuScalar, usize),
+    /// Used mostly for the body of enum variants.
+    InlineStruct(String),
+    Ref(Box<GpuType>),
+    /// An `Option<Ref<T>>`, stored as the offset or `NULL_REF` for `None`.
+    NullableRef(Box<GpuType>),
+    /// A variable-length array, stored as a header of length and offset.
+    Slice(Box<GpuType>),
+    /// A fixed-size array of structs, refs or vectors; scalar arrays are vectors.
+    Array(Box<GpuType>, usize),
+    /// A 2D affine transform, from `Affine2`, stored as the six coefficients of
+    /// `kurbo::Affine` and unpacked to a 2x3 matrix.
+    Affine2,
+}
+
+/// The shape of an enum variant as written in the schema.
+#[derive(Clone, Copy, PartialEq)]
+enum VariantKind {
+    Unit,
+    Tuple,
+    Named,
+}
+
+#[derive(Clone)]
+struct GpuVariant {
+    name: String,
+    kind: VariantKind,
+    /// Fields of tuple variants are named `f0`, `f1`, and so on.
+    fields: Vec<(String, GpuType)>,
+    /// Whether the struct of a `Variant(Struct)` holds the tag; derived and
+    /// imported structs can't, as their layout is fixed.
+    wraps_struct: bool,
+    /// The value of the tag, from `Variant = N` or one more than the previous.
+    tag: u32,
+}
+
+#[derive(Clone)]
+struct GpuEnum {
+    name: String,
+    variants: Vec<GpuVariant>,
+    /// Size of the tag in bytes, from `#[tag(u8)]` or `#[tag(u16)]`; 4 by
+    /// default. Smaller tags leave the rest of the first word to the fields of
+    /// variant structs.
+    tag_size: usize,
+}
+
+#[derive(Clone)]
+enum GpuTypeDef {
+    Struct(String, Vec<(String, GpuType)>),
+    Enum(GpuEnum),
+}
+
+struct GpuModule {
+    name: String,
+    attrs: HashSet<String>,
+    /// Item names that are used as enum variants, with the size of their tag.
+    enum_variants: HashMap<String, usize>,
+    /// Structs generated to hold the fields of enum variants; these have no
+    /// Rust type of their own.
+    variant_structs: HashSet<String>,
+    /// Types from `#[derive(PietGpu)]` or other modules; their Rust types
+    /// are defined elsewhere.
+    externs: HashSet<String>,
+    /// The `use` items importing types from other modules.
+    uses: Vec<syn::ItemUse>,
+    /// The module defining each type, which names its include guard.
+    def_modules: HashMap<String, String>,
+    /// Layout attributes of each struct and enum, including variant structs.
+    type_attrs: HashMap<String, TypeAttrs>,
+    defs: Vec<GpuTypeDef>,
+    bindings: Bindings,
+}
+
+/// How the fields of a struct are aligned, set by `#[layout(..)]` on a module.
+#[derive(Clone, Copy, PartialEq)]
+enum LayoutMode {
+    /// Everything is aligned to 4 bytes; the default.
+    Scalar,
+    /// Vectors and structs are aligned as in GLSL's std430 layout.
+    Std430,
+}
+
+/// Where a module's buffers are bound in WGSL, set by `#[bindings(..)]` on
+/// the module, as in `#[bindings(group = 1, buf = 2, dst_buf = 3)]`.
+///
+/// The defaults are group 0 and bindings 0 and 1, and `dst_buf` defaults to
+/// the binding after `buf`. GLSL has `<MODULE>_BUF_BINDING` and
+/// `<MODULE>_DST_BUF_BINDING` defines instead.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Bindings {
+    group: u32,
+    buf: u32,
+    dst_buf: u32,
+}
+
+impl Default for Bindings {
+    fn default() -> Bindings {
+        Bindings {
+            group: 0,
+            buf: 0,
+            dst_buf: 1,
+        }
+    }
+}
+
+/// Layout attributes of a struct or enum.
+#[derive(Clone)]
+struct TypeAttrs {
+    mode: LayoutMode,
+    /// From `#[align(N)]`.
+    align: Option<usize>,
+    /// Cleared by `#[pack(none)]`, which gives each small field its own uint.
+    pack: bool,
+    /// Attributes of the fields that have any.
+    fields: HashMap<String, FieldAttrs>,
+}
+
+/// Layout attributes of a field.
+#[derive(Clone, Copy)]
+struct FieldAttrs {
+    align: Option<usize>,
+    pack: bool,
+}
+
+/// A `#[unorm]` or `#[snorm]` attribute.
+///
+/// The storage type can be given as in `#[unorm(u8)]`, for fields that are
+/// `f32` in Rust, as in `#[derive(PietGpu)]` types.
+#[derive(Clone)]
+struct NormAttr {
+    signed: bool,
+    storage: Option<GpuScalar>,
+}
+
+/// The values of the layout attributes in a list of attributes.
+#[derive(Default)]
+struct LayoutAttrValues {
+    mode: Option<LayoutMode>,
+    align: Option<usize>,
+    pack: Option<bool>,
+}
+
+/// A type defined outside of a module, by `#[derive(PietGpu)]` or in another
+/// `piet_gpu!` module.
+#[derive(Clone)]
+struct ExternType {
+    name: String,
+    /// The definitions of the type, after those of the types it uses, and
+    /// of the enums holding it.
+    defs: Vec<GpuTypeDef>,
+    variant_structs: HashSet<String>,
+    type_attrs: HashMap<String, TypeAttrs>,
+    def_modules: HashMap<String, String>,
+}
+
+impl GpuScalar {
+    fn size(self) -> usize {
+        match self {
+            GpuScalar::F32 | GpuScalar::I32 | GpuScalar::U32 => 4,
+            GpuScalar::I8 | GpuScalar::U8 | GpuScalar::Unorm8 | GpuScalar::Snorm8 => 1,
+            GpuScalar::I16
+            | GpuScalar::U16
+            | GpuScalar::F16
+            | GpuScalar::Unorm16
+            | GpuScalar::Snorm16 => 2,
+            GpuScalar::Bool | GpuScalar::UBits(..) | GpuScalar::IBits(..) => self.bits().div_ceil(8),
+        }
+    }
+
+    /// Size in bits, which is only less than `8 * size()` for bit fields.
+    fn bits(self) -> usize {
+        match self {
+            GpuScalar::Bool => 1,
+            GpuScalar::UBits(n, _) | GpuScalar::IBits(n, _) => n as usize,
+            _ => 8 * self.size(),
+        }
+    }
+
+    fn is_bitfield(self) -> bool {
+        matches!(self, GpuScalar::Bool | GpuScalar::UBits(..) | GpuScalar::IBits(..))
+    }
+
+    /// The bit field of width `bits` stored in this integer, for `#[bits(N)]`.
+    fn with_bits(self, bits: usize) -> Option<GpuScalar> {
+        if bits == 0 || bits >= self.bits() {
+            return None;
+        }
+        match self {
+            GpuScalar::U8 | GpuScalar::U16 | GpuScalar::U32 => {
+                Some(GpuScalar::UBits(bits as u8, self.size() as u8))
+            }
+            GpuScalar::I8 | GpuScalar::I16 | GpuScalar::I32 => {
+                Some(GpuScalar::IBits(bits as u8, self.size() as u8))
+            }
+            _ => None,
+        }
+    }
+
+    /// The stored value of 1.0 for normalized scalars.
+    fn norm_scale(self) -> Option<f32> {
+        match self {
+            GpuScalar::Unorm8 => Some(255.0),
+            GpuScalar::Unorm16 => Some(65535.0),
+            GpuScalar::Snorm8 => Some(127.0),
+            GpuScalar::Snorm16 => Some(32767.0),
+            _ => None,
+        }
+    }
+
+    fn is_snorm(self) -> bool {
+        self == GpuScalar::Snorm8 || self == GpuScalar::Snorm16
+    }
+
+    /// The normalized scalar stored in this one, for `#[unorm]` or `#[snorm]`.
+    fn normalized(self, signed: bool) -> Option<GpuScalar> {
+        match (self, signed) {
+            (GpuScalar::U8, false) => Some(GpuScalar::Unorm8),
+            (GpuScalar::U16, false) => Some(GpuScalar::Unorm16),
+            (GpuScalar::U8, true) | (GpuScalar::I8, true) => Some(GpuScalar::Snorm8),
+            (GpuScalar::U16, true) | (GpuScalar::I16, true) => Some(GpuScalar::Snorm16),
+            _ => None,
+        }
+    }
+
+    /// The scalar as written in the schema.
+    fn schema_name(self) -> String {
+        let name = match self {
+            GpuScalar::F32 => "f32",
+            GpuScalar::I8 => "i8",
+            GpuScalar::I16 => "i16",
+            GpuScalar::I32 => "i32",
+            GpuScalar::U8 => "u8",
+            GpuScalar::U16 => "u16",
+            GpuScalar::U32 => "u32",
+            GpuScalar::F16 => "f16",
+            GpuScalar::Unorm8 => "unorm8",
+            GpuScalar::Unorm16 => "unorm16",
+            GpuScalar::Snorm8 => "snorm8",
+            GpuScalar::Snorm16 => "snorm16",
+            GpuScalar::Bool => "bool",
+            GpuScalar::UBits(n, _) => return format!("u{}", n),
+            GpuScalar::IBits(n, _) => return format!("i{}", n),
+        };
+        name.into()
+    }
+}
+
+impl std::fmt::Display for GpuScalar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GpuScalar::F32 => write!(f, "F32"),
+            GpuScalar::I8 => write!(f, "I8"),
+            GpuScalar::I16 => write!(f, "I16"),
+            GpuScalar::I32 => write!(f, "I32"),
+            GpuScalar::U8 => write!(f, "U8"),
+            GpuScalar::U16 => write!(f, "U16"),
+            GpuScalar::U32 => write!(f, "U32"),
+            GpuScalar::F16 => write!(f, "F16"),
+            GpuScalar::Unorm8 => write!(f, "Unorm8"),
+            GpuScalar::Unorm16 => write!(f, "Unorm16"),
+            GpuScalar::Snorm8 => write!(f, "Snorm8"),
+            GpuScalar::Snorm16 => write!(f, "Snorm16"),
+            GpuScalar::Bool => write!(f, "Bool"),
+            GpuScalar::UBits(n, _) => write!(f, "UBits({})", n),
+            GpuScalar::IBits(n, _) => write!(f, "IBits({})", n),
+        }
+    }
+}
+
+impl GpuType {
+    /// The type as written in the schema.
+    fn schema_name(&self) -> String {
+        match self {
+            GpuType::Scalar(scalar) => scalar.schema_name(),
+            GpuType::Vector(scalar, size) => format!("[{}; {}]", scalar.schema_name(), size),
+            GpuType::InlineStruct(name) => name.clone(),
+            GpuType::Ref(inner) => format!("Ref<{}>", inner.schema_name()),
+            GpuType::NullableRef(inner) => format!("Option<Ref<{}>>", inner.schema_name()),
+            GpuType::Slice(elem) => format!("[{}]", elem.schema_name()),
+            GpuType::Array(elem, len) => format!("[{}; {}]", elem.schema_name(), len),
+            GpuType::Affine2 => "Affine2".into(),
+        }
+    }
+
+    /// The name of the struct or enum this type refers to, if any.
+    fn type_name(&self) -> Option<&str> {
+        match self {
+            GpuType::InlineStruct(name) => Some(name),
+            GpuType::Ref(inner)
+            | GpuType::NullableRef(inner)
+            | GpuType::Slice(inner)
+            | GpuType::Array(inner, _) => inner.type_name(),
+            GpuType::Scalar(_) | GpuType::Vector(..) | GpuType::Affine2 => None,
+        }
+    }
+}
+
+impl GpuVariant {
+    /// The struct of a variant of the form `Variant(Struct)`, which holds the
+    /// tag itself.
+    fn wrapped_struct(&self) -> Option<&str> {
+        if self.wraps_struct {
+            if let GpuType::InlineStruct(name) = &self.fields[0].1 {
+                return Some(name);
+            }
+        }
+        None
+    }
+
+    /// The struct holding the tag and fields of the variant; unit variants
+    /// have none.
+    fn struct_name(&self, enum_name: &str) -> Option<String> {
+        if let Some(name) = self.wrapped_struct() {
+            Some(name.to_string())
+        } else if self.fields.is_empty() {
+            None
+        } else {
+            Some(format!("{}{}", enum_name, self.name))
+        }
+    }
+}
+
+impl GpuTypeDef {
+    fn name(&self) -> &str {
+        match self {
+            GpuTypeDef::Struct(name, _) => name,
+            GpuTypeDef::Enum(en) => &en.name,
+        }
+    }
+
+    /// The types of all fields of the definition.
+    fn field_types(&self) -> Vec<&GpuType> {
+        match self {
+            GpuTypeDef::Struct(_, fields) => fields.iter().map(|(_, ty)| ty).collect(),
+            GpuTypeDef::Enum(en) => en
+                .variants
+                .iter()
+                .flat_map(|variant| variant.fields.iter().map(|(_, ty)| ty))
+                .collect(),
+        }
+    }
+
+    fn collect_refs(&self, enum_variants: &mut HashMap<String, usize>) {
+        if let GpuTypeDef::Enum(en) = self {
+            for variant in &en.variants {
+                if let Some(name) = variant.struct_name(&en.name) {
+                    enum_variants.insert(name, en.tag_size);
+                }
+            }
+        }
+    }
+}
+
+impl GpuModule {
+    /// The tag of the only enum variant holding the struct `name`, if any.
+    fn variant_tag(&self, name: &str) -> Option<u32> {
+        let mut variants = self
+            .defs
+            .iter()
+            .filter_map(|def| match def {
+                GpuTypeDef::Enum(en) => Some(en),
+                _ => None,
+            })
+            .flat_map(|en| {
+                en.variants
+                    .iter()
+                    .filter(move |variant| variant.struct_name(&en.name).as_deref() == Some(name))
+            });
+        match (variants.next(), variants.next()) {
+            (Some(variant), None) => Some(variant.tag),
+            _ => None,
+        }
+    }
+
+    fn resolve_by_name(&self, name: &str) -> Result<&GpuTypeDef, String> {
+        for def in &self.defs {
+            if def.name() == name {
+                return Ok(def);
+            }
+        }
+        Err(format!("could not find {} in module", name))
+    }
+}
+
+/// Expand `#[derive(PietGpu)]` on a struct or enum.
+pub fn derive_piet_gpu(input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let mut resolver = Resolver::for_crate();
+    match syn::parse2(input).and_then(|input| derive_piet_gpu_impl(input, &mut resolver)) {
+        Ok(mut expanded) => {
+            expanded.extend(resolver.gen_dependencies());
+            expanded
+        }
+        Err(err) => err.to_compile_error(),
+    }
+}
+
+fn derive_piet_gpu_impl(
+    input: syn::Item,
+    resolver: &mut Resolver,
+) -> Result<proc_macro2::TokenStream, syn::Error> {
+    let (ident, generics) = match &input {
+        syn::Item::Struct(s) => (&s.ident, &s.generics),
+        syn::Item::Enum(e) => (&e.ident, &e.generics),
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "only structs and enums are supported by piet_gpu",
+            ))
+        }
+    };
+    if !generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(generics, "piet_gpu types can't be generic"));
+    }
+    let module = derived_module(&input, resolver)?;
+    let (_, impls) = module.defs.last().unwrap().gen_rust(&module);
+    let views = module
+        .defs
+        .iter()
+        .filter(|def| !module.externs.contains(def.name()))
+        .map(|def| def.gen_view(&module));
+    let shader_match = module.gen_shader_match();
+    Ok(quote! {
+        #impls
+        #(#views)*
+
+        impl #ident {
+            /// The shader code for the type, and the derived types it uses.
+            pub fn gpu_source(lang: &str) -> String {
+                #shader_match
+            }
+        }
+    })
+}
+
+/// Expand a `piet_gpu!` module.
+pub fn piet_gpu(input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let input: syn::ItemMod = match syn::parse2(input) {
+        Ok(input) => input,
+        Err(e) => return e.to_compile_error(),
+    };
+    let mut resolver = Resolver::for_crate();
+    let module = match GpuModule::from_syn(&input, &mut resolver) {
+        Ok(module) => module,
+        Err(e) => return e.to_compile_error(),
+    };
+    let gen_gpu_fn = format_ident!("gen_gpu_{}", input.ident);
+    let layout_fn = format_ident!("layout_{}", input.ident);
+    let hash_id = format_ident!("{}_SCHEMA_HASH", module.name.to_uppercase());
+    let module_id = &input.ident;
+    let layout = module.gen_layout();
+    let shader_match = module.gen_shader_match();
+    let mut expanded = quote! {
+        pub fn #gen_gpu_fn(lang: &str) -> String {
+            #shader_match
+        }
+
+        /// The hash of the layout of the module, as in the shader code.
+        pub use self::#module_id::SCHEMA_HASH as #hash_id;
+
+        /// The layout of the types in the module, as used by the generated shaders.
+        pub fn #layout_fn() -> crate::layout::ModuleLayout {
+            #layout
+        }
+    };
+    expanded.extend(module.gen_derive());
+    expanded.extend(resolver.gen_dependencies());
+    if module.attrs.contains("rust_encode") {
+        let check_fn = format_ident!("check_layout_{}", module.name);
+        expanded.extend(quote! {
+            pub use self::#module_id::check_layout as #check_fn;
+        });
+    }
+    expanded
+}
+
+fn to_snake_case(mut str: &str) -> String {
+    let mut words = vec![];
+    // Preserve leading underscores
+    str = str.trim_start_matches(|c: char| {
+        if c == '_' {
+            words.push(String::new());
+            true
+        } else {
+            false
+        }
+    });
+    for s in str.split('_') {
+        let mut last_upper = false;
+        let mut buf = String::new();
+        if s.is_empty() {
+            continue;
+        }
+        for ch in s.chars() {
+            if !buf.is_empty() && buf != "'" && ch.is_uppercase() && !last_upper {
+                words.push(buf);
+                buf = String::new();
+            }
+            last_upper = ch.is_uppercase();
+            buf.extend(ch.to_lowercase());
+        }
+        words.push(buf);
+    }
+    words.join("_")
+}
+
+fn to_camel_case(str: &str) -> String {
+    str.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The error `Schema` reports for `src`, as `line:column: message`.
+    fn schema_error(src: &str) -> String {
+        Schema::new().add_source(src).unwrap_err()
+    }
+
+    /// The shader code of the module `m` in `src`.
+    fn shader(src: &str, target: TargetLang) -> String {
+        let mut schema = Schema::new();
+        schema.add_source(src).unwrap();
+        schema.gen_shader("m", target).unwrap()
+    }
+
+    /// The error for a module holding a struct with one field of type `ty`.
+    fn field_error(ty: &str) -> String {
+        schema_error(&format!("piet_gpu! {{ mod m {{ struct S {{ a: u32, b: {} }} }} }}", ty))
+    }
+
+    #[test]
+    fn vector_lengths() {
+        for ty in &["[f32; 5]", "[u32; 0]", "[u8; 7]"] {
+            let err = field_error(ty);
+            assert!(err.ends_with("must have 1 to 4 elements"), "{}: {}", ty, err);
+        }
+        assert!(field_error("[Ref<S>; 0]").ends_with("at least one element"));
+        // The error points at the length.
+        assert!(field_error("[f32; 5]").starts_with("1:49:"));
+    }
+
+    #[test]
+    fn duplicate_fields() {
+        assert!(field_error("f32, a: u16").ends_with("field `a` is already declared"));
+        let err = schema_error("piet_gpu! { mod m { enum E { V { x: u8, x: u8 } } } }");
+        assert_eq!(err, "1:41: field `x` is already declared");
+    }
+
+    #[test]
+    fn empty_types() {
+        let err = schema_error("piet_gpu! { mod m { struct S {} } }");
+        assert_eq!(err, "1:28: structs must have at least one field");
+        let err = schema_error("piet_gpu! { mod m { enum E {} } }");
+        assert_eq!(err, "1:26: enums must have at least one variant");
+        // Unit variants are fine.
+        assert!(Schema::new().add_source("piet_gpu! { mod m { enum E { A, B {} } } }").is_ok());
+    }
+
+    #[test]
+    fn field_types() {
+        // Field types start at column 43.
+        let cases = [
+            ("X", "1:43: unknown type `X`"),
+            ("Ref<X>", "1:43: unknown type `X`"),
+            ("Box<u32>", "1:43: unsupported type for piet_gpu"),
+            ("(u32, u32)", "1:43: unsupported type for piet_gpu"),
+            ("Ref<u32, u32>", "1:43: `Ref` takes exactly one type argument"),
+            ("[f32; N]", "1:49: array length must be an integer literal"),
+            ("[Affine2; 2]", "1:44: can't deal with arrays of `Affine2`"),
+            ("[Slice<u32>; 2]", "1:44: can't deal with arrays of slices or arrays"),
+            ("Slice<Slice<u32>>", "1:49: can't deal with nested slices"),
+        ];
+        for (ty, expected) in &cases {
+            assert_eq!(&field_error(ty), expected, "{}", ty);
+        }
+        let err = schema_error("piet_gpu! { mod m { enum E { A } struct S { e: E } } }");
+        assert_eq!(err, "1:48: enum `E` can't be stored inline; use `Ref<E>`");
+        let err = schema_error("piet_gpu! { mod m { struct S { t: T } struct T { x: u32 } } }");
+        assert_eq!(err, "1:35: `T` must be defined before it is stored inline");
+        // Refs can point forward.
+        assert!(Schema::new()
+            .add_source("piet_gpu! { mod m { struct S { t: Ref<T> } struct T { x: u32 } } }")
+            .is_ok());
+    }
+
+    #[test]
+    fn definitions() {
+        let err = schema_error("piet_gpu! { mod m { struct S { x: u32 } enum S { A } } }");
+        assert_eq!(err, "1:46: `S` is defined more than once");
+        let err =
+            schema_error("piet_gpu! { mod m { struct EA { x: u32 } enum E { A { y: u32 } } } }");
+        assert_eq!(err, "1:51: variant needs a struct named `EA`, which is already defined");
+        let err = schema_error("piet_gpu! { mod m { type T = u32; } }");
+        assert_eq!(err, "1:21: only structs and enums are supported by piet_gpu");
+        let err = schema_error("piet_gpu! { mod m { use crate::n::T as U; } }");
+        assert_eq!(err, "1:35: imported types can't be renamed");
+        let err = schema_error("piet_gpu! { mod m { use std; } }");
+        assert!(err.starts_with("1:25: expected a type of another piet_gpu module"), "{}", err);
+    }
+
+    #[test]
+    fn bit_fields() {
+        let fields = "on: bool, #[bits(4)] level: u8, #[bits(5)] delta: i8";
+        let hlsl = shader(
+            &format!("piet_gpu! {{ mod m {{ struct S {{ {} }} }} }}", fields),
+            TargetLang::Hlsl,
+        );
+        // Bit fields share a word, and signed ones are sign-extended.
+        assert!(hlsl.contains("result = bool(extract_1bit_value(0, on_level_delta));"), "{}", hlsl);
+        assert!(hlsl.contains("result = extract_4bit_value(1, on_level_delta);"));
+        assert!(hlsl.contains("result = extract_5bit_signed_value(5, on_level_delta);"));
+        assert!(hlsl.contains("result = insert_5bit_value(5, result, uint(delta));"));
+        let too_wide = "`#[bits]` needs an integer type wider than the bit field";
+        for ty in &["[u8; 2]", "u8", "f32"] {
+            let err = field_error(&format!("u32, #[bits(8)] x: {}", ty));
+            assert!(err.ends_with(too_wide), "{}", err);
+        }
+        assert_eq!(field_error("u32, #[bits(0)] x: u32"), format!("1:55: {}", too_wide));
+        assert_eq!(field_error("u32, #[bits(x)] x: u32"), "1:55: expected a number of bits");
+        assert_eq!(field_error("[bool; 2]"), "1:44: can't deal with arrays of bit fields");
+    }
+
+    #[test]
+    fn normalized() {
+        let fields = "#[unorm] u: u16, #[snorm(i16)] n: f32, #[snorm] s: [i8; 2]";
+        let hlsl = shader(
+            &format!("piet_gpu! {{ mod m {{ struct S {{ {} }} }} }}", fields),
+            TargetLang::Hlsl,
+        );
+        let reads = [
+            "result = float(extract_16bit_value(0, u_n)) / 65535.0;",
+            "result = max(float(extract_16bit_signed_value(16, u_n)) / 32767.0, float(-1.0));",
+            // Writers clamp and round.
+            "uint(int(round(clamp(s[1], float(-1.0), float(1.0)) * 127.0)))",
+        ];
+        for read in &reads {
+            assert!(hlsl.contains(read), "{}\n{}", read, hlsl);
+        }
+        assert_eq!(
+            field_error("u32, #[unorm] x: u32"),
+            "1:60: `#[unorm]` needs a u8 or u16, or a vector of them"
+        );
+        assert_eq!(
+            field_error("u32, #[unorm] x: f32"),
+            "1:60: the stored type of an `f32` field must be given, as in `#[unorm(u8)]`"
+        );
+        let err = field_error("u32, #[unorm(u32)] x: f32");
+        assert_eq!(err, "1:56: expected an 8 or 16 bit integer type");
+    }
+
+    #[test]
+    fn derived_types() {
+        // Derived types are named by their paths, and can be used before they
+        // are defined.
+        let mut schema = Schema::new();
+        schema
+            .add_source(
+                "piet_gpu! { mod m { use crate::{A, B}; struct S { a: Ref<B>, b: A } } }
+                #[derive(PietGpu)] struct A { x: u32 }
+                #[derive(PietGpu)] #[uses(crate::A)] enum B { P(A), Q }",
+            )
+            .unwrap();
+        let hlsl = schema.gen_shader("m", TargetLang::Hlsl).unwrap();
+        assert!(hlsl.contains("inline APacked A_read("));
+        assert!(hlsl.contains("inline uint B_tag("));
+        let err = schema_error(
+            "#[derive(PietGpu)] #[uses(crate::B)] struct A { b: Ref<B> }
+            #[derive(PietGpu)] #[uses(crate::A)] struct B { a: Ref<A> }",
+        );
+        assert!(
+            err.ends_with("piet_gpu types can't use each other: crate::B -> crate::A -> crate::B"),
+            "{}",
+            err
+        );
+        // Types deriving `PietGpu` in other modules are only used when named.
+        let src = "#[derive(PietGpu)] struct A { x: u32 }
+            mod other { #[derive(PietGpu)] struct A { y: u32 } }";
+        let err = schema_error(&format!("piet_gpu! {{ mod m {{ struct S {{ a: A }} }} }} {}", src));
+        assert_eq!(err, "1:35: unknown type `A`");
+        let module = "piet_gpu! { mod m { use crate::other::A; struct S { a: A } } }";
+        let mut schema = Schema::new();
+        schema.add_source(&format!("{} {}", module, src)).unwrap();
+        assert!(schema.gen_shader("m", TargetLang::Hlsl).unwrap().contains("uint y = "));
+        let err = schema_error("#[derive(PietGpu)] #[uses(crate::A = 1)] struct B { x: u32 }");
+        assert!(err.starts_with("1:27: expected the path of a type"), "{}", err);
+    }
+
+    #[test]
+    fn imports() {
+        // Modules can import from modules that come after them.
+        let mut schema = Schema::new();
+        schema
+            .add_source(
+                "piet_gpu! { mod a { use crate::b::B; struct A { b: B } } }
+                piet_gpu! { mod b { struct B { x: u32 } } }",
+            )
+            .unwrap();
+        assert!(schema.gen_shader("a", TargetLang::Hlsl).unwrap().contains("BPacked B_read("));
+        let err = schema_error(
+            "piet_gpu! { mod a { use crate::b::C; } } piet_gpu! { mod b { struct B { x: u32 } } }",
+        );
+        assert_eq!(err, "1:35: module `crate::b` has no type `C`");
+        let err = schema_error("piet_gpu! { mod a { use crate::c::C; } }");
+        assert_eq!(err, "1:32: no `c` in `crate`");
+        let err = schema_error("piet_gpu! { mod a { use other_crate::b::B; } }");
+        assert!(err.starts_with("1:25: types are imported by their path from the crate"), "{}", err);
+        let err = schema_error(
+            "piet_gpu! { mod a { use crate::b::B; struct A { x: u32 } } }
+            piet_gpu! { mod b { use crate::a::A; struct B { x: u32 } } }",
+        );
+        let cycle = "piet_gpu types can't use each other: crate::b -> crate::a -> crate::b";
+        assert!(err.ends_with(cycle), "{}", err);
+    }
+
+    #[test]
+    fn imports_from_files() {
+        // Paths are followed through `mod` declarations, `#[path]` and re-exports.
+        let dir = std::env::temp_dir().join(format!("piet-gpu-imports-{}", std::process::id()));
+        let files = [
+            (
+                "lib.rs",
+                "mod shapes;
+                #[path = \"extra/colors.rs\"] mod colors;
+                #[path = \"gone.rs\"] mod gone;
+                mod missing;",
+            ),
+            ("shapes/mod.rs", "mod inner; pub use self::inner::Circle;"),
+            ("shapes/inner.rs", "#[derive(PietGpu)] pub struct Circle { r: f32 }"),
+            (
+                "extra/colors.rs",
+                "pub use self::color::*; piet_gpu! { mod color { struct Color { c: u32 } } }",
+            ),
+        ];
+        for (name, src) in &files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, src).unwrap();
+        }
+        let lib = dir.join("lib.rs");
+        let add = |src: &str| {
+            let mut schema = Schema::new();
+            schema.add_file(&lib).unwrap();
+            schema.add_source(src).map(|_| schema)
+        };
+        let schema = add(
+            "piet_gpu! { mod m { use crate::shapes::Circle; use crate::colors::Color;
+            struct S { a: Circle, b: Color } } }",
+        )
+        .unwrap();
+        let hlsl = schema.gen_shader("m", TargetLang::Hlsl).unwrap();
+        assert!(hlsl.contains("inline CirclePacked Circle_read("), "{}", hlsl);
+        assert!(hlsl.contains("inline ColorPacked Color_read("), "{}", hlsl);
+        let err = add("piet_gpu! { mod m { use crate::shapes::Square; } }").err().unwrap();
+        let shapes = dir.join("shapes/mod.rs");
+        assert_eq!(
+            err,
+            format!("1:40: no `Square` in `crate::shapes` (searched {})", shapes.display())
+        );
+        let err = add("piet_gpu! { mod m { use crate::gone::X; } }").err().unwrap();
+        assert_eq!(
+            err,
+            format!(
+                "1:32: can't follow `#[path = \"gone.rs\"]` of `mod gone` in {}: there is no {}",
+                lib.display(),
+                dir.join("gone.rs").display()
+            )
+        );
+        let err = add("piet_gpu! { mod m { use crate::missing::X; } }").err().unwrap();
+        assert_eq!(
+            err,
+            format!(
+                "1:32: can't find the file of `mod missing;` in {}: there is no {} or {}",
+                lib.display(),
+                dir.join("missing.rs").display(),
+                dir.join("missing/mod.rs").display()
+            )
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn imported_variant_structs() {
+        // A struct held by an enum variant keeps the tag word when imported alone.
+        let mut schema = Schema::new();
+        schema
+            .add_source(
+                "piet_gpu! { mod a { struct P { x: u32 } enum E { Nop, P(P) } } }
+                piet_gpu! { mod b { use crate::a::P; struct Q { p: Ref<P> } } }",
+            )
+            .unwrap();
+        let hlsl = schema.gen_shader("b", TargetLang::Hlsl).unwrap();
+        assert!(hlsl.contains("uint x = buf.Load(ref + 4);"), "{}", hlsl);
+        assert!(!hlsl.contains("#define P_SIZE"));
+    }
+
+    #[test]
+    fn kernel_shaders() {
+        let mut schema = Schema::new();
+        schema
+            .add_source(
+                "piet_gpu! { mod a { struct P { x: u32 } } }
+                piet_gpu! { mod b { use crate::a::P; struct Q { p: P } } }",
+            )
+            .unwrap();
+        // The imported type's readers use the importing module's buffer.
+        let glsl = schema.gen_shader("b", TargetLang::Glsl).unwrap();
+        assert!(glsl.contains("PPacked b_P_read(PRef ref) {"));
+        assert!(glsl.contains("uint x = b_buf[ref >> 2];"));
+        assert!(glsl.contains("PPacked p = b_P_read(ref);"));
+        assert!(!glsl.contains(" P_read("));
+        let hlsl = schema.gen_shader("b", TargetLang::Hlsl).unwrap();
+        assert!(hlsl.contains("PPacked p = P_read(buf, ref);"));
+        // Each definition is included once.
+        let wgsl = schema.gen_kernel_shader(&["a", "b"], TargetLang::Wgsl).unwrap();
+        let decls = ["struct PPacked ", "fn P_read(", "fn b_P_read(", "alias PRef ", "const P_SIZE:"];
+        for decl in &decls {
+            assert_eq!(wgsl.matches(decl).count(), 1, "{}", decl);
+        }
+        assert!(schema.gen_kernel_shader(&["a", "c"], TargetLang::Wgsl).is_none());
+    }
+
+    #[test]
+    fn wgsl_bindings() {
+        let mut schema = Schema::new();
+        schema
+            .add_source(
+                "piet_gpu! { mod a { struct P { x: u32 } } }
+                piet_gpu! { #[bindings(group = 1, buf = 2)] mod b { struct Q { x: u32 } } }
+                piet_gpu! { #[bindings(buf = 4, dst_buf = 3)] mod c { struct R { x: u32 } } }",
+            )
+            .unwrap();
+        let wgsl = schema.gen_kernel_shader(&["a", "b", "c"], TargetLang::Wgsl).unwrap();
+        for decl in &[
+            "@group(0) @binding(0) var<storage, read> a_buf: array<u32>;",
+            "@group(0) @binding(1) var<storage, read_write> a_dst_buf: array<u32>;",
+            "@group(1) @binding(2) var<storage, read> b_buf: array<u32>;",
+            "@group(1) @binding(3) var<storage, read_write> b_dst_buf: array<u32>;",
+            "@group(0) @binding(4) var<storage, read> c_buf: array<u32>;",
+            "@group(0) @binding(3) var<storage, read_write> c_dst_buf: array<u32>;",
+        ] {
+            assert!(wgsl.contains(decl), "{}", decl);
+        }
+        let err = schema_error("piet_gpu! { #[bindings(buf = 1, dst_buf = 1)] mod m { } }");
+        assert_eq!(err, "1:13: the buffers need different bindings");
+        let err = schema_error("piet_gpu! { #[bindings(set = 1)] mod m { } }");
+        assert_eq!(err, "1:24: expected `group`, `buf` or `dst_buf`");
+        let err = schema_error("piet_gpu! { #[bindings(group)] mod m { } }");
+        assert_eq!(err, "1:24: expected `name = N`");
+    }
+
+    #[test]
+    fn glsl_writers_need_dst_binding() {
+        let mut schema = Schema::new();
+        schema
+            .add_source("piet_gpu! { mod m { struct S { a: u32 } enum E { A(S) } } }")
+            .unwrap();
+        let glsl = schema.gen_shader("m", TargetLang::Glsl).unwrap();
+        // Every use of the writable buffer is inside an `#ifdef`.
+        let mut depth = 0;
+        for line in glsl.lines() {
+            if line == "#ifdef M_DST_BUF_BINDING" || (depth > 0 && line.starts_with("#if")) {
+                depth += 1;
+            } else if depth > 0 && line == "#endif" {
+                depth -= 1;
+            }
+            assert!(depth > 0 || !line.contains("m_dst_buf"), "{}", line);
+        }
+        assert!(glsl.contains("void S_write("));
+        assert!(glsl.contains("void E_write_tag("));
+        assert!(glsl.contains("void S_copy_range("));
+        let hlsl = schema.gen_shader("m", TargetLang::Hlsl).unwrap();
+        assert!(!hlsl.contains("DST_BUF_BINDING"));
+    }
+
+    #[test]
+    fn narrow_tags() {
+        let src = "piet_gpu! { mod m {
+            struct D { k: u8, x: f32 }
+            #[tag(u8)]
+            enum E { A = 3, D(D), B { b: u16 }, C = 200 }
+        } }";
+        let hlsl = shader(src, TargetLang::Hlsl);
+        assert!(hlsl.contains("uint result = extract_8bit_value(0, buf.Load(ref));"));
+        assert!(hlsl.contains("buf.Store(ref, insert_8bit_value(0, buf.Load(ref), tag));"));
+        // Tags without a discriminant follow the one before.
+        for define in &["#define E_A 3\n", "#define E_D 4\n", "#define E_B 5\n", "#define E_C 200\n"] {
+            assert!(hlsl.contains(define), "{}", define);
+        }
+        // The first fields share the tag's word, which holds the tag when packed.
+        assert!(hlsl.contains("result = extract_8bit_value(8, k);"));
+        assert!(hlsl.contains("float x = asfloat(buf.Load(ref + 4));"));
+        assert!(hlsl.contains("inline uint D_pack_k(uint k) {\n    uint result = 4;\n"));
+        assert!(hlsl.contains("buf.Store(ref, s.k);"));
+        let wgsl = shader(src, TargetLang::Wgsl);
+        assert!(wgsl.contains("var result: u32 = extract_8bit_value(0u, m_buf[ref_ >> 2u]);"));
+    }
+
+    #[test]
+    fn variant_tags_are_written() {
+        let src = "piet_gpu! { mod m {
+            struct S { x: u32 }
+            enum E { A { y: f32 } = 7, B(S), C(S) }
+        } }";
+        // `_pack` fills in the tag and `_write` stores it in the word
+        // `E_tag` reads back; `_read` loads it too.
+        let cases = [
+            (
+                TargetLang::Hlsl,
+                "result.tag = 7;",
+                "buf.Store(ref, s.tag);",
+                "uint result = buf.Load(ref);",
+                "result.tag = buf.Load(ref);",
+            ),
+            (
+                TargetLang::Glsl,
+                "result.tag = 7;",
+                "m_dst_buf[ref >> 2] = s.tag;",
+                "uint result = m_buf[ref >> 2];",
+                "result.tag = m_buf[ref >> 2];",
+            ),
+            (
+                TargetLang::Wgsl,
+                "result.tag = 7u;",
+                "m_dst_buf[ref_ >> 2u] = s.tag;",
+                "var result: u32 = m_buf[ref_ >> 2u];",
+                "result.tag = m_buf[ref_ >> 2u];",
+            ),
+            (
+                TargetLang::Msl,
+                "result.tag = 7;",
+                "*(device uint*)(buf + ref) = s.tag;",
+                "uint result = *(device const uint*)(buf + ref);",
+                "result.tag = *(device const uint*)(buf + ref);",
+            ),
+        ];
+        for (target, pack, write, tag, read) in &cases {
+            let code = shader(src, *target);
+            for line in &[pack, write, tag, read] {
+                assert!(code.contains(*line), "{:?}: {}", target, line);
+            }
+            // `S` is held by two variants, so it's packed with the tag given.
+            assert!(code.contains("result.tag = tag;"), "{:?}", target);
+        }
+        let hlsl = shader(src, TargetLang::Hlsl);
+        assert!(hlsl.contains("inline SPacked S_pack(S unpacked, uint tag) {"));
+        assert!(hlsl.contains("inline EAPacked EA_pack(EA unpacked) {"));
+        // With a narrow tag, the packer of the shared word takes it instead.
+        let src = "piet_gpu! { mod m { struct S { x: u8 } #[tag(u8)] enum E { A(S), B(S) } } }";
+        let hlsl = shader(src, TargetLang::Hlsl);
+        let pack_x = "inline uint S_pack_x(uint x, uint tag) {\n    uint result = tag;\n";
+        assert!(hlsl.contains(pack_x));
+        assert!(hlsl.contains("result.x = S_pack_x(unpacked.x, tag);"));
+    }
+
+    #[test]
+    fn tag_errors() {
+        let err = schema_error("piet_gpu! { mod m { #[tag(u8)] enum E { A = 256 } } }");
+        assert_eq!(err, "1:45: tag doesn't fit in `u8`");
+        let err = schema_error("piet_gpu! { mod m { #[tag(u16)] enum E { A = 65535, B } } }");
+        assert_eq!(err, "1:53: tag doesn't fit in `u16`");
+        // `C` follows `B`, taking the tag of `A`.
+        let err = schema_error("piet_gpu! { mod m { enum E { A = 1, B = 0, C } } }");
+        assert_eq!(err, "1:44: tag 1 is already used by `A`");
+        let err = schema_error("piet_gpu! { mod m { #[tag(u64)] enum E { A } } }");
+        assert_eq!(err, "1:21: expected `#[tag(u8|u16|u32)]`");
+        let err = schema_error("piet_gpu! { mod m { #[tag(u8)] struct S { a: u32 } } }");
+        assert_eq!(err, "1:21: `#[tag]` is only allowed on enums");
+        let err = schema_error(
+            "piet_gpu! { mod m { struct S { a: u32 } enum E { A(S) } #[tag(u8)] enum F { B(S) } } }",
+        );
+        assert_eq!(err, "1:77: `S` is already wrapped by an enum with a `u32` tag");
+    }
+
+    #[test]
+    fn nullable_refs() {
+        let src = "piet_gpu! { mod m { struct C { parent: Option<Ref<C>>, depth: u32 } } }";
+        let hlsl = shader(src, TargetLang::Hlsl);
+        assert!(hlsl.contains("CRef parent = buf.Load(ref);"));
+        assert!(hlsl.contains("inline bool C_parent_is_null(ByteAddressBuffer buf, CRef ref) {"));
+        assert!(hlsl.contains("return C_parent(buf, ref) == 0xffffffffu;"));
+        let glsl = shader(src, TargetLang::Glsl);
+        assert!(glsl.contains("bool C_parent_is_null(CRef ref) {"));
+        let wgsl = shader(src, TargetLang::Wgsl);
+        assert!(wgsl.contains("fn C_parent_is_null(ref_: CRef) -> bool {"));
+        let msl = shader(src, TargetLang::Msl);
+        assert!(msl.contains("inline bool C_parent_is_null(const device char *buf, CRef ref) {"));
+        assert!(field_error("Option<u32>").ends_with("only `Option<Ref<T>>` is supported"));
+        assert!(field_error("Option<Slice<u32>>").ends_with("only `Option<Ref<T>>` is supported"));
+    }
+
+    #[test]
+    fn msl_packed_slices() {
+        // The slice header follows a word, so it's only 4 aligned; a `uint2`
+        // would be padded to 8 and the struct would no longer match the encoding.
+        let src = "piet_gpu! { mod m { struct T { a: u32, s: Slice<u32> } } }";
+        let msl = shader(src, TargetLang::Msl);
+        assert!(msl.contains("struct TPacked {\n    uint a;\n    packed_uint2 s;\n};"));
+        assert!(msl.contains("*(device packed_uint2*)(buf + ref + 4) = packed_uint2(s.s);"));
+        assert!(msl.contains("#define T_SIZE 12\n"));
+        // The unpacked struct keeps the plain vector.
+        assert!(msl.contains("struct T {\n    uint a;\n    uint2 s;\n};"));
+    }
+
+    #[test]
+    fn copy_helpers() {
+        // A struct of 28 bytes with the tag of the variant holding it, an enum
+        // and the struct of its other variant.
+        let src = "piet_gpu! { mod m {
+            struct S { a: u32, b: [f32; 4], c: u16 }
+            enum E { A(S), B { x: u32 } }
+        } }";
+        let hlsl = shader(src, TargetLang::Hlsl);
+        for ty in &["S", "E", "EB"] {
+            for suffix in &["copy", "copy_rw", "copy_range", "copy_range_rw"] {
+                let name = format!("inline void {}_{}(", ty, suffix);
+                assert!(hlsl.contains(&name), "{}", name);
+            }
+        }
+        // Copies move the largest groups of words the buffer allows.
+        assert!(hlsl.contains("uint4 group0 = src.Load4(src_ref);\n    dst.Store4(dst_ref, group0);\n"));
+        assert!(hlsl.contains("uint3 group1 = src.Load3(src_ref + 16);"));
+        assert!(hlsl.contains("S_copy(src, src_ref + i * 28, dst, dst_ref + i * 28);"));
+        let glsl = shader(src, TargetLang::Glsl);
+        assert!(glsl.contains("void S_copy(uint src_ref, uint dst_ref) {"));
+        assert!(glsl.contains("m_dst_buf[dst_ix + 6] = m_buf[src_ix + 6];"));
+        assert!(!glsl.contains("m_dst_buf[dst_ix + 7] = m_buf[src_ix + 7];"));
+        let wgsl = shader(src, TargetLang::Wgsl);
+        assert!(wgsl.contains("S_copy(src_ref + i * 28u, dst_ref + i * 28u);"));
+        let msl = shader(src, TargetLang::Msl);
+        let header = "inline void S_copy(const device char *src, uint src_ref, device char *dst, uint dst_ref) {";
+        assert!(msl.contains(header));
+        assert!(msl.contains("d[6] = s[6];"));
+    }
+
+    #[test]
+    fn std430_layout() {
+        let types = "struct P { id: u8, pos: [f32; 3], vel: [f32; 2], w: f32, hdr: [f32; 4] }
+            struct Q { p: P, x: u32 }";
+        let src = format!("piet_gpu! {{ #[layout(std430)] mod m {{ {} }} }}", types);
+        let hlsl = shader(&src, TargetLang::Hlsl);
+        // Vectors are aligned to their size, with 3-vectors aligned as 4-vectors,
+        // and structs to their largest member.
+        for load in &[
+            "uint id = buf.Load(ref);",
+            "float3 pos = asfloat(buf.Load3(ref + 16));",
+            "float2 vel = asfloat(buf.Load2(ref + 32));",
+            "float w = asfloat(buf.Load(ref + 40));",
+            "float4 hdr = asfloat(buf.Load4(ref + 48));",
+            "uint x = buf.Load(ref + 64);",
+        ] {
+            assert!(hlsl.contains(load), "{}", load);
+        }
+        assert!(hlsl.contains("#define P_SIZE 64\n"));
+        assert!(hlsl.contains("#define Q_SIZE 80\n"));
+        // The scalar layout only aligns to words.
+        let hlsl = shader(&format!("piet_gpu! {{ mod m {{ {} }} }}", types), TargetLang::Hlsl);
+        assert!(hlsl.contains("float3 pos = asfloat(buf.Load3(ref + 4));"));
+        assert!(hlsl.contains("#define P_SIZE 44\n"));
+        let err = schema_error("piet_gpu! { #[layout(std430)] mod m { struct S { a: [[f32; 3]; 2] } } }");
+        assert_eq!(err, "1:53: arrays of 3-vectors aren't supported with `#[layout(std430)]`");
+        let err = schema_error("piet_gpu! { mod m { struct S { #[align(6)] a: u32 } } }");
+        assert_eq!(err, "1:40: alignment must be a power of two of at least 4");
+        let err = schema_error("piet_gpu! { #[layout(std140)] mod m { struct S { a: u32 } } }");
+        assert_eq!(err, "1:22: unsupported argument to `#[layout]`");
+    }
+
+    #[test]
+    fn affine_alignment() {
+        // The columns are loaded as uint2s at word offsets, so the scalar
+        // layout packs an `Affine2` after a word in every target.
+        let src = "piet_gpu! { mod m { struct S { a: u32, m: Affine2 } } }";
+        for (target, load) in &[
+            (TargetLang::Hlsl, "asfloat(buf.Load2(ref + 4))"),
+            (TargetLang::Msl, "*(device const packed_uint2*)(buf + ref + 4)"),
+            (TargetLang::Glsl, "uvec2(m_buf[(ref >> 2) + 1], m_buf[(ref >> 2) + 2])"),
+            (TargetLang::Wgsl, "vec2<u32>(m_buf[(ref_ >> 2u) + 1u], m_buf[(ref_ >> 2u) + 2u])"),
+        ] {
+            let code = shader(src, *target);
+            assert!(code.contains(load), "{}", load);
+            assert!(code.contains("#define S_SIZE 28\n") || code.contains("S_SIZE: u32 = 28u;"));
+        }
+        let src = "piet_gpu! { #[layout(std430)] mod m { struct S { a: u32, m: Affine2 } } }";
+        let hlsl = shader(src, TargetLang::Hlsl);
+        assert!(hlsl.contains("asfloat(buf.Load2(ref + 8))"));
+        assert!(hlsl.contains("#define S_SIZE 32\n"));
+    }
+
+    #[test]
+    fn half_floats() {
+        // Each half is in the low or high 16 bits of a word, and `pack2x16float`
+        // and its kin get a zero for the half they leave out.
+        let src = "piet_gpu! { mod m { struct S { a: f16, b: [f16; 3] } } }";
+        for (target, unpacks, packs) in &[
+            (
+                TargetLang::Hlsl,
+                ["f16tof32(extract_16bit_value(0, a))", "f16tof32(extract_16bit_value(16, b[0]))"],
+                ["insert_16bit_value(0, result, f32tof16(a))", "f32tof16(b[2])"],
+            ),
+            (
+                TargetLang::Msl,
+                [
+                    "as_type<half>(ushort(extract_16bit_value(0, a)))",
+                    "as_type<half>(ushort(extract_16bit_value(16, b[0])))",
+                ],
+                ["uint(as_type<ushort>(a))", "uint(as_type<ushort>(b[2]))"],
+            ),
+            (
+                TargetLang::Glsl,
+                [
+                    "unpackHalf2x16(extract_16bit_value(0, a)).x",
+                    "unpackHalf2x16(extract_16bit_value(16, b[0])).x",
+                ],
+                ["packHalf2x16(vec2(a, 0.0))", "packHalf2x16(vec2(b[2], 0.0))"],
+            ),
+            (
+                TargetLang::Wgsl,
+                [
+                    "unpack2x16float(extract_16bit_value(0u, a)).x",
+                    "unpack2x16float(extract_16bit_value(16u, b[0])).x",
+                ],
+                ["pack2x16float(vec2<f32>(a, 0.0))", "pack2x16float(vec2<f32>(b[2], 0.0))"],
+            ),
+        ] {
+            let code = shader(src, *target);
+            for expr in unpacks.iter().chain(packs) {
+                assert!(code.contains(expr), "{}", expr);
+            }
+        }
+        let msl = shader(src, TargetLang::Msl);
+        assert!(msl.contains("struct S {\n    half a;\n    half3 b;\n"));
+        let hlsl = shader(src, TargetLang::Hlsl);
+        assert!(hlsl.contains("struct S {\n    float a;\n    float3 b;\n"));
+    }
+}
@@ -0,0 +1,184 @@
+//! Metal Shading Language, reading from `device char *` buffers passed to the
+//! functions.
+
+use std::fmt::Write;
+
+use crate::backend::{offset_tail, Backend};
+use crate::layout::size_in_uints;
+use crate::shader::vector_size_str;
+use crate::{GpuModule, GpuScalar, GpuTypeDef};
+
+pub(crate) struct Msl;
+
+impl Backend for Msl {
+    fn buf_and_ref_args(&self, ref_type: &str) -> String {
+        format!("const device char *buf, {}", self.param(ref_type, self.ref_name()))
+    }
+
+    fn rw_buf_and_ref_args(&self, ref_type: &str) -> String {
+        format!("device char *buf, {}", self.param(ref_type, self.ref_name()))
+    }
+
+    fn passes_buffers(&self) -> bool {
+        true
+    }
+
+    fn buf_call_arg(&self) -> &'static str {
+        "buf, "
+    }
+
+    fn fn_header(&self, ret_type: &str, name: &str, params: &str) -> String {
+        format!("inline {} {}({}) {{\n", ret_type, name, params)
+    }
+
+    fn has_small_scalars(&self) -> bool {
+        true
+    }
+
+    /// Metal vectors are aligned to their size; packed vectors keep the
+    /// struct layout identical to the encoded layout.
+    fn packed_vector_typename(&self, typename: String) -> String {
+        format!("packed_{}", typename)
+    }
+
+    fn bits_to_value(&self, scalar: GpuScalar, inner: &str, size: usize) -> String {
+        let size_str = vector_size_str(size);
+        match scalar {
+            GpuScalar::F32 => format!("as_type<float{}>({})", size_str, inner),
+            GpuScalar::I32 => format!("as_type<int{}>({})", size_str, inner),
+            GpuScalar::U32 => inner.into(),
+            GpuScalar::F16 => format!("as_type<half>(ushort({}))", inner),
+            // Metal has native 8 and 16 bit types, so narrow the extracted value.
+            _ => format!("{}({})", self.vector_typename(scalar, size), inner),
+        }
+    }
+
+    fn value_to_bits(&self, scalar: GpuScalar, inner: &str, size: usize) -> String {
+        match scalar {
+            GpuScalar::F32 | GpuScalar::I32 => {
+                format!("as_type<uint{}>({})", vector_size_str(size), inner)
+            }
+            GpuScalar::F16 => format!("uint(as_type<ushort>({}))", inner),
+            _ => format!("{}({})", self.vector_typename(GpuScalar::U32, size), inner),
+        }
+    }
+
+    fn affine_typename(&self) -> &'static str {
+        "float3x2"
+    }
+
+    fn load_expr_at(
+        &self,
+        _module: &GpuModule,
+        ref_name: &str,
+        offset: usize,
+        size: usize,
+    ) -> String {
+        let tail = offset_tail(offset);
+        if size == 1 {
+            format!("*(device const uint*)(buf + {}{})", ref_name, tail)
+        } else {
+            // Packed vectors are only 4 byte aligned, like the encoded data.
+            let size_str = vector_size_str(size);
+            format!(
+                "uint{}(*(device const packed_uint{}*)(buf + {}{}))",
+                size_str, size_str, ref_name, tail
+            )
+        }
+    }
+
+    fn rw_load_expr(&self, _module: &GpuModule, offset: usize) -> String {
+        format!(
+            "*(device uint*)(buf + {})",
+            self.add_offset(self.ref_name(), offset)
+        )
+    }
+
+    fn store_stmt_at(
+        &self,
+        _module: &GpuModule,
+        ref_name: &str,
+        offset: usize,
+        size: usize,
+        value: &str,
+    ) -> String {
+        let tail = offset_tail(offset);
+        if size == 1 {
+            format!("    *(device uint*)(buf + {}{}) = {};\n", ref_name, tail, value)
+        } else {
+            let size_str = vector_size_str(size);
+            format!(
+                "    *(device packed_uint{}*)(buf + {}{}) = packed_uint{}({});\n",
+                size_str, ref_name, tail, size_str, value
+            )
+        }
+    }
+
+    fn copy_params(&self, rw: bool) -> (String, &'static str) {
+        (
+            format!(
+                "{}device char *src, uint src_ref, device char *dst, uint dst_ref",
+                if rw { "" } else { "const " }
+            ),
+            "src, {}, dst, {}",
+        )
+    }
+
+    fn copy_body(&self, _module: &GpuModule, size: usize, rw: bool) -> String {
+        let mut r = String::new();
+        writeln!(
+            r,
+            "    {}device uint *s = ({}device uint *)(src + src_ref);",
+            if rw { "" } else { "const " },
+            if rw { "" } else { "const " }
+        )
+            .unwrap();
+        writeln!(r, "    device uint *d = (device uint *)(dst + dst_ref);").unwrap();
+        for i in 0..size_in_uints(size) {
+            writeln!(r, "    d[{}] = s[{}];", i, i).unwrap();
+        }
+        r
+    }
+
+    /// Enum loaders that rely on the packed structs having exactly the
+    /// encoded layout, so a whole struct can be moved at once.
+    fn def_extras(&self, def: &GpuTypeDef) -> String {
+        let mut r = String::new();
+        match def {
+            GpuTypeDef::Struct(..) => (),
+            GpuTypeDef::Enum(en) => {
+                write!(
+                    r,
+                    "{}",
+                    self.fn_header(
+                        &en.name,
+                        &format!("{}_read", en.name),
+                        &self.buf_and_ref_args(&format!("{}Ref", en.name)),
+                    )
+                )
+                    .unwrap();
+                writeln!(r, "    return *(device const {} *)(buf + ref);", en.name).unwrap();
+                write!(r, "}}\n\n").unwrap();
+
+                // Variant loaders reinterpret a copy of the enum as the variant's packed struct.
+                for variant in &en.variants {
+                    if let Some(name) = variant.struct_name(&en.name) {
+                        write!(
+                            r,
+                            "{}",
+                            self.fn_header(
+                                &format!("{}Packed", name),
+                                &format!("{}_load", name),
+                                &format!("const thread {} &s", en.name),
+                            )
+                        )
+                            .unwrap();
+                        writeln!(r, "    return *((const thread {}Packed *)&s);", name).unwrap();
+                        write!(r, "}}\n\n").unwrap();
+                    }
+                }
+            }
+        }
+        r
+    }
+}
@@ -0,0 +1,1042 @@
+//! Parsing of the schema from the items of `piet_gpu!` modules and derives.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+
+use syn::{
+    Expr, ExprLit, Fields, FieldsNamed, FieldsUnnamed, GenericArgument, ItemEnum, ItemStruct, Lit,
+    Meta, MetaList, MetaNameValue, NestedMeta, PathArguments, TypeArray, TypePath, TypeSlice,
+};
+
+use crate::resolve::Resolver;
+use crate::{
+    Bindings, ExternType, FieldAttrs, GpuEnum, GpuModule, GpuScalar, GpuType, GpuTypeDef,
+    GpuVariant, LayoutAttrValues, LayoutMode, NormAttr, TypeAttrs, VariantKind,
+};
+
+impl GpuScalar {
+    pub(crate) fn from_syn(ty: &syn::Type) -> Option<Self> {
+        ty_as_single_ident(ty).and_then(|ident| GpuScalar::from_name(&ident))
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "f32" => Some(GpuScalar::F32),
+            "f16" => Some(GpuScalar::F16),
+            "i8" => Some(GpuScalar::I8),
+            "i16" => Some(GpuScalar::I16),
+            "i32" => Some(GpuScalar::I32),
+            "u8" => Some(GpuScalar::U8),
+            "u16" => Some(GpuScalar::U16),
+            "u32" => Some(GpuScalar::U32),
+            "bool" => Some(GpuScalar::Bool),
+            // Bit fields `u1` to `u31`.
+            _ => name
+                .strip_prefix('u')
+                .and_then(|bits| bits.parse().ok())
+                .filter(|bits| (1..32).contains(bits) && name == format!("u{}", bits))
+                .map(|bits| GpuScalar::UBits(bits, bits.div_ceil(8))),
+        }
+    }
+}
+
+impl GpuType {
+    pub(crate) fn from_syn(ty: &syn::Type) -> syn::Result<Self> {
+        if let Some(scalar) = GpuScalar::from_syn(ty) {
+            return Ok(GpuType::Scalar(scalar));
+        }
+        if let Some(name) = ty_as_single_ident(ty) {
+            if name == "Affine2" {
+                return Ok(GpuType::Affine2);
+            }
+            // Names are resolved against the module by `ItemNames::check`.
+            return Ok(GpuType::InlineStruct(name));
+        }
+        match ty {
+            syn::Type::Path(TypePath {
+                                path: syn::Path { segments, .. },
+                                ..
+                            }) => {
+                if segments.len() == 1 {
+                    let seg = &segments[0];
+                    // `Slice<T>` is the Rust type of `[T]`, as seen by `#[derive(PietGpu)]`.
+                    if seg.ident == "Ref" || seg.ident == "Slice" || seg.ident == "Option" {
+                        if let PathArguments::AngleBracketed(args) = &seg.arguments {
+                            if args.args.len() == 1 {
+                                if let GenericArgument::Type(inner) = &args.args[0] {
+                                    let inner_ty = GpuType::from_syn(inner)?;
+                                    if seg.ident == "Slice" {
+                                        return GpuType::from_syn_slice(inner, inner_ty);
+                                    }
+                                    if seg.ident == "Option" {
+                                        return match inner_ty {
+                                            GpuType::Ref(target) => Ok(GpuType::NullableRef(target)),
+                                            _ => Err(syn::Error::new_spanned(
+                                                inner,
+                                                "only `Option<Ref<T>>` is supported",
+                                            )),
+                                        };
+                                    }
+                                    return Ok(GpuType::Ref(Box::new(inner_ty)));
+                                }
+                            }
+                        }
+                        return Err(syn::Error::new_spanned(
+                            seg,
+                            format!("`{}` takes exactly one type argument", seg.ident),
+                        ));
+                    }
+                }
+                Err(syn::Error::new_spanned(ty, "unsupported type for piet_gpu"))
+            }
+            syn::Type::Array(TypeArray { elem, len: len_expr, .. }) => {
+                let len = match expr_int_lit(len_expr) {
+                    Some(len) => len,
+                    None => {
+                        return Err(syn::Error::new_spanned(
+                            len_expr,
+                            "array length must be an integer literal",
+                        ))
+                    }
+                };
+                if let Some(scalar) = GpuScalar::from_syn(elem) {
+                    if scalar.is_bitfield() {
+                        return Err(syn::Error::new_spanned(elem, "can't deal with arrays of bit fields"));
+                    }
+                    // Scalar arrays are vectors, which have at most 4 components.
+                    if !(1..=4).contains(&len) {
+                        return Err(syn::Error::new_spanned(
+                            len_expr,
+                            "arrays of scalars are vectors, which must have 1 to 4 elements",
+                        ));
+                    }
+                    Ok(GpuType::Vector(scalar, len))
+                } else if len == 0 {
+                    Err(syn::Error::new_spanned(len_expr, "arrays must have at least one element"))
+                } else {
+                    match GpuType::from_syn(elem)? {
+                        GpuType::Vector(scalar, _) if scalar.size() < 4 => Err(
+                            syn::Error::new_spanned(elem, "can't deal with arrays of small vectors"),
+                        ),
+                        elem @ GpuType::InlineStruct(_)
+                        | elem @ GpuType::Ref(_)
+                        | elem @ GpuType::NullableRef(_)
+                        | elem @ GpuType::Vector(..) => Ok(GpuType::Array(Box::new(elem), len)),
+                        GpuType::Affine2 => Err(syn::Error::new_spanned(
+                            elem,
+                            "can't deal with arrays of `Affine2`",
+                        )),
+                        _ => Err(syn::Error::new_spanned(
+                            elem,
+                            "can't deal with arrays of slices or arrays",
+                        )),
+                    }
+                }
+            }
+            syn::Type::Slice(TypeSlice { elem: syn_elem, .. }) => {
+                let elem = GpuType::from_syn(syn_elem)?;
+                GpuType::from_syn_slice(syn_elem, elem)
+            }
+            _ => Err(syn::Error::new_spanned(ty, "unsupported type for piet_gpu")),
+        }
+    }
+
+    /// Parse the type of a field, which is normalized by `#[unorm]` or
+    /// `#[snorm]` on the field, or on its type where it applies.
+    pub(crate) fn from_syn_field(
+        field: &syn::Field,
+        type_norm: Option<NormAttr>,
+        names: &ItemNames,
+    ) -> syn::Result<Self> {
+        let mut ty = GpuType::from_syn(&field.ty)?;
+        names.check(&ty, &field.ty)?;
+        if let Some(lit) = bits_attr(&field.attrs)? {
+            let bits = lit.base10_parse()?;
+            ty = match ty {
+                GpuType::Scalar(scalar) => scalar.with_bits(bits).map(GpuType::Scalar),
+                _ => None,
+            }
+            .ok_or_else(|| {
+                syn::Error::new_spanned(&lit, "`#[bits]` needs an integer type wider than the bit field")
+            })?;
+        }
+        match NormAttr::from_syn(&field.attrs)? {
+            Some(norm) => ty.normalized(&norm).ok_or_else(|| {
+                let is_f32 = match ty {
+                    GpuType::Scalar(s) | GpuType::Vector(s, _) => s == GpuScalar::F32,
+                    _ => false,
+                };
+                let msg = if is_f32 && norm.storage.is_none() {
+                    "the stored type of an `f32` field must be given, as in `#[unorm(u8)]`"
+                } else if norm.signed {
+                    "`#[snorm]` needs an 8 or 16 bit integer, or a vector of them"
+                } else {
+                    "`#[unorm]` needs a u8 or u16, or a vector of them"
+                };
+                syn::Error::new_spanned(&field.ty, msg)
+            }),
+            None => Ok(type_norm
+                .and_then(|norm| ty.normalized(&norm))
+                .unwrap_or(ty)),
+        }
+    }
+
+    /// This type with its small integers stored as normalized values.
+    pub(crate) fn normalized(&self, norm: &NormAttr) -> Option<Self> {
+        match self {
+            GpuType::Scalar(scalar) => norm.apply(*scalar).map(GpuType::Scalar),
+            GpuType::Vector(scalar, size) => norm.apply(*scalar).map(|s| GpuType::Vector(s, *size)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn from_syn_slice(syn_elem: &syn::Type, elem: GpuType) -> syn::Result<Self> {
+        match &elem {
+            GpuType::Vector(scalar, _) if scalar.size() < 4 => Err(syn::Error::new_spanned(
+                syn_elem,
+                "can't deal with slices of small vectors",
+            )),
+            GpuType::Scalar(scalar) if scalar.is_bitfield() => Err(syn::Error::new_spanned(
+                syn_elem,
+                "can't deal with slices of bit fields",
+            )),
+            GpuType::Slice(_) => Err(syn::Error::new_spanned(
+                syn_elem,
+                "can't deal with nested slices",
+            )),
+            GpuType::Affine2 => Err(syn::Error::new_spanned(
+                syn_elem,
+                "can't deal with slices of `Affine2`",
+            )),
+            _ => Ok(GpuType::Slice(Box::new(elem))),
+        }
+    }
+}
+
+/// Names of the items in a module, used to check the types fields refer to.
+pub(crate) struct ItemNames {
+    /// All items in the module; these can be the target of a `Ref`.
+    all: HashSet<String>,
+    enums: HashSet<String>,
+    /// Structs defined so far; only these can be stored inline.
+    defined: HashSet<String>,
+    /// Extern types not shadowed by items of the module.
+    externs: HashSet<String>,
+}
+
+impl ItemNames {
+    pub(crate) fn new(items: &[syn::Item], externs: &[ExternType]) -> Self {
+        let mut all = HashSet::new();
+        let mut enums = HashSet::new();
+        let mut defined = HashSet::new();
+        let mut extern_names = HashSet::new();
+        for item in items {
+            match item {
+                syn::Item::Struct(s) => {
+                    all.insert(s.ident.to_string());
+                }
+                syn::Item::Enum(e) => {
+                    all.insert(e.ident.to_string());
+                    enums.insert(e.ident.to_string());
+                }
+                _ => (),
+            }
+        }
+        // Items of the module shadow extern types of the same name.
+        for ty in externs {
+            if all.insert(ty.name.clone()) {
+                if let Some(GpuTypeDef::Enum(_)) = ty.defs.iter().find(|def| def.name() == ty.name) {
+                    enums.insert(ty.name.clone());
+                }
+                defined.insert(ty.name.clone());
+                extern_names.insert(ty.name.clone());
+            }
+        }
+        ItemNames {
+            all,
+            enums,
+            defined,
+            externs: extern_names,
+        }
+    }
+
+    /// Check that the names in a field type resolve; `span` is the field's syn type.
+    pub(crate) fn check(&self, ty: &GpuType, span: &syn::Type) -> syn::Result<()> {
+        match ty {
+            GpuType::InlineStruct(name) => {
+                if !self.all.contains(name) {
+                    Err(syn::Error::new_spanned(span, format!("unknown type `{}`", name)))
+                } else if self.enums.contains(name) {
+                    Err(syn::Error::new_spanned(
+                        span,
+                        format!("enum `{}` can't be stored inline; use `Ref<{}>`", name, name),
+                    ))
+                } else if !self.defined.contains(name) {
+                    Err(syn::Error::new_spanned(
+                        span,
+                        format!("`{}` must be defined before it is stored inline", name),
+                    ))
+                } else {
+                    Ok(())
+                }
+            }
+            GpuType::Ref(inner) | GpuType::NullableRef(inner) | GpuType::Slice(inner) => {
+                match inner.deref() {
+                    GpuType::InlineStruct(name) if !self.all.contains(name) => Err(
+                        syn::Error::new_spanned(span, format!("unknown type `{}`", name)),
+                    ),
+                    GpuType::InlineStruct(_) => Ok(()),
+                    inner => self.check(inner, span),
+                }
+            }
+            GpuType::Array(elem, _) => self.check(elem, span),
+            GpuType::Scalar(_) | GpuType::Vector(..) | GpuType::Affine2 => Ok(()),
+        }
+    }
+}
+
+impl LayoutAttrValues {
+    /// Parse the `layout`, `align` and `pack` attributes, which are an error
+    /// unless in `allowed`. Other attributes are ignored.
+    pub(crate) fn from_syn(attrs: &[syn::Attribute], allowed: &[&str]) -> syn::Result<Self> {
+        let mut values = LayoutAttrValues::default();
+        for attr in attrs {
+            let name = match path_as_single_ident(&attr.path) {
+                Some(name) if ["layout", "align", "pack"].contains(&name.as_str()) => name,
+                _ => continue,
+            };
+            if !allowed.contains(&name.as_str()) {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    format!("`#[{}]` isn't allowed here", name),
+                ));
+            }
+            let arg = match attr.parse_meta()? {
+                Meta::List(MetaList { nested, .. }) if nested.len() == 1 => nested[0].clone(),
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        attr,
+                        format!("expected `#[{}(..)]`", name),
+                    ))
+                }
+            };
+            match (name.as_str(), &arg) {
+                ("align", NestedMeta::Lit(Lit::Int(lit))) => {
+                    let align: usize = lit.base10_parse()?;
+                    if align < 4 || !align.is_power_of_two() {
+                        return Err(syn::Error::new_spanned(
+                            lit,
+                            "alignment must be a power of two of at least 4",
+                        ));
+                    }
+                    values.align = Some(align);
+                }
+                ("pack", NestedMeta::Meta(Meta::Path(path))) if path.is_ident("none") => {
+                    values.pack = Some(false);
+                }
+                ("layout", NestedMeta::Meta(Meta::Path(path))) if path.is_ident("scalar") => {
+                    values.mode = Some(LayoutMode::Scalar);
+                }
+                ("layout", NestedMeta::Meta(Meta::Path(path))) if path.is_ident("std430") => {
+                    values.mode = Some(LayoutMode::Std430);
+                }
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        arg,
+                        format!("unsupported argument to `#[{}]`", name),
+                    ))
+                }
+            }
+        }
+        Ok(values)
+    }
+}
+
+/// Parse a `#[bits(N)]` attribute, giving the width of an integer field.
+pub(crate) fn bits_attr(attrs: &[syn::Attribute]) -> syn::Result<Option<syn::LitInt>> {
+    for attr in attrs {
+        if attr.path.is_ident("bits") {
+            return match attr.parse_meta()? {
+                Meta::List(MetaList { nested, .. }) if nested.len() == 1 => match &nested[0] {
+                    NestedMeta::Lit(Lit::Int(lit)) => Ok(Some(lit.clone())),
+                    arg => Err(syn::Error::new_spanned(arg, "expected a number of bits")),
+                },
+                _ => Err(syn::Error::new_spanned(attr, "expected `#[bits(..)]`")),
+            };
+        }
+    }
+    Ok(None)
+}
+
+/// Parse a `#[tag(u8)]` attribute, giving the size of an enum's tag in bytes.
+pub(crate) fn tag_size_attr(attrs: &[syn::Attribute]) -> syn::Result<Option<usize>> {
+    for attr in attrs {
+        if attr.path.is_ident("tag") {
+            let size = match attr.parse_meta()? {
+                Meta::List(MetaList { nested, .. }) if nested.len() == 1 => match &nested[0] {
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("u8") => Some(1),
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("u16") => Some(2),
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("u32") => Some(4),
+                    _ => None,
+                },
+                _ => None,
+            };
+            return match size {
+                Some(size) => Ok(Some(size)),
+                None => Err(syn::Error::new_spanned(attr, "expected `#[tag(u8|u16|u32)]`")),
+            };
+        }
+    }
+    Ok(None)
+}
+
+/// Parse a `#[bindings(..)]` attribute of a module, giving where its buffers
+/// are bound in WGSL.
+pub(crate) fn bindings_attr(attrs: &[syn::Attribute]) -> syn::Result<Bindings> {
+    let mut bindings = Bindings::default();
+    for attr in attrs {
+        if !attr.path.is_ident("bindings") {
+            continue;
+        }
+        let nested = match attr.parse_meta()? {
+            Meta::List(MetaList { nested, .. }) if !nested.is_empty() => nested,
+            _ => return Err(syn::Error::new_spanned(attr, "expected `#[bindings(..)]`")),
+        };
+        let mut dst_buf = None;
+        for arg in &nested {
+            let (key, lit) = match arg {
+                NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                    path,
+                    lit: Lit::Int(lit),
+                    ..
+                })) => (path_as_single_ident(path), lit),
+                _ => return Err(syn::Error::new_spanned(arg, "expected `name = N`")),
+            };
+            let value = lit.base10_parse()?;
+            match key.as_deref() {
+                Some("group") => bindings.group = value,
+                Some("buf") => bindings.buf = value,
+                Some("dst_buf") => dst_buf = Some(value),
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        arg,
+                        "expected `group`, `buf` or `dst_buf`",
+                    ))
+                }
+            }
+        }
+        bindings.dst_buf = dst_buf.unwrap_or(bindings.buf + 1);
+        if bindings.dst_buf == bindings.buf {
+            return Err(syn::Error::new_spanned(attr, "the buffers need different bindings"));
+        }
+    }
+    Ok(bindings)
+}
+
+/// The Rust type of a tag of `tag_size` bytes.
+pub(crate) fn tag_type_name(tag_size: usize) -> &'static str {
+    match tag_size {
+        1 => "u8",
+        2 => "u16",
+        _ => "u32",
+    }
+}
+
+impl NormAttr {
+    pub(crate) fn from_syn(attrs: &[syn::Attribute]) -> syn::Result<Option<Self>> {
+        let mut result = None;
+        for attr in attrs {
+            let signed = match path_as_single_ident(&attr.path).as_deref() {
+                Some("unorm") => false,
+                Some("snorm") => true,
+                _ => continue,
+            };
+            let storage = match attr.parse_meta()? {
+                Meta::Path(_) => None,
+                Meta::List(MetaList { nested, .. }) => {
+                    let scalar = match nested.first() {
+                        Some(NestedMeta::Meta(Meta::Path(path))) if nested.len() == 1 => path
+                            .get_ident()
+                            .and_then(|ident| GpuScalar::from_name(&ident.to_string())),
+                        _ => None,
+                    };
+                    match scalar {
+                        Some(scalar) if scalar.size() < 4 => Some(scalar),
+                        _ => {
+                            return Err(syn::Error::new_spanned(
+                                nested,
+                                "expected an 8 or 16 bit integer type",
+                            ))
+                        }
+                    }
+                }
+                meta => return Err(syn::Error::new_spanned(meta, "unexpected attribute form")),
+            };
+            result = Some(NormAttr { signed, storage });
+        }
+        Ok(result)
+    }
+
+    /// The normalized scalar for a field of the given scalar type.
+    pub(crate) fn apply(&self, scalar: GpuScalar) -> Option<GpuScalar> {
+        match (self.storage, scalar) {
+            (Some(storage), GpuScalar::F32) => storage.normalized(self.signed),
+            (None, _) => scalar.normalized(self.signed),
+            _ => None,
+        }
+    }
+}
+
+impl TypeAttrs {
+    /// Parse the attributes of a struct, or of an enum if `fields` is `None`.
+    pub(crate) fn from_syn(
+        attrs: &[syn::Attribute],
+        fields: Option<&Fields>,
+        mode: LayoutMode,
+    ) -> syn::Result<Self> {
+        let values = LayoutAttrValues::from_syn(attrs, &["layout", "align", "pack"])?;
+        let mut type_attrs = TypeAttrs {
+            mode: values.mode.unwrap_or(mode),
+            align: values.align,
+            pack: values.pack.unwrap_or(true),
+            fields: HashMap::new(),
+        };
+        if let Some(fields) = fields {
+            type_attrs.add_fields(fields)?;
+        }
+        Ok(type_attrs)
+    }
+
+    /// Attributes for the struct holding the fields of a variant of this enum.
+    pub(crate) fn variant(&self, variant: &syn::Variant) -> syn::Result<Self> {
+        LayoutAttrValues::from_syn(&variant.attrs, &[])?;
+        let mut type_attrs = TypeAttrs {
+            fields: HashMap::new(),
+            ..self.clone()
+        };
+        type_attrs.add_fields(&variant.fields)?;
+        Ok(type_attrs)
+    }
+
+    pub(crate) fn add_fields(&mut self, fields: &Fields) -> syn::Result<()> {
+        for (ix, field) in fields.iter().enumerate() {
+            let name = match &field.ident {
+                Some(ident) => ident.to_string(),
+                None => format!("f{}", ix),
+            };
+            // Arrays are strided by the element size, which std430 rounds up for these.
+            if self.mode == LayoutMode::Std430 {
+                if let GpuType::Array(elem, _) = GpuType::from_syn(&field.ty)? {
+                    if let GpuType::Vector(_, 3) = *elem {
+                        return Err(syn::Error::new_spanned(
+                            &field.ty,
+                            "arrays of 3-vectors aren't supported with `#[layout(std430)]`",
+                        ));
+                    }
+                }
+            }
+            let values = LayoutAttrValues::from_syn(&field.attrs, &["align", "pack"])?;
+            if values.align.is_some() || values.pack.is_some() {
+                let field_attrs = FieldAttrs {
+                    align: values.align,
+                    pack: values.pack.unwrap_or(true),
+                };
+                self.fields.insert(name, field_attrs);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn field(&self, name: &str) -> FieldAttrs {
+        self.fields.get(name).copied().unwrap_or(FieldAttrs {
+            align: None,
+            pack: true,
+        })
+    }
+}
+
+impl GpuTypeDef {
+    pub(crate) fn from_syn(item: &syn::Item, names: &ItemNames) -> syn::Result<Self> {
+        match item {
+            syn::Item::Struct(ItemStruct {
+                                  attrs,
+                                  ident,
+                                  fields: Fields::Named(FieldsNamed { named, .. }),
+                                  ..
+                              }) => {
+                if let Some(attr) = attrs.iter().find(|attr| attr.path.is_ident("tag")) {
+                    return Err(syn::Error::new_spanned(attr, "`#[tag]` is only allowed on enums"));
+                }
+                if named.is_empty() {
+                    return Err(syn::Error::new_spanned(ident, "structs must have at least one field"));
+                }
+                let type_norm = NormAttr::from_syn(attrs)?;
+                let mut fields = Vec::new();
+                for field in named {
+                    let field_ty = GpuType::from_syn_field(field, type_norm.clone(), names)?;
+                    push_named_field(&mut fields, field, field_ty)?;
+                }
+                Ok(GpuTypeDef::Struct(ident.to_string(), fields))
+            }
+            syn::Item::Struct(ItemStruct { ident, .. }) => Err(syn::Error::new_spanned(
+                ident,
+                "structs in a piet_gpu! module must have named fields",
+            )),
+            syn::Item::Enum(ItemEnum {
+                                attrs, ident, variants, ..
+                            }) => {
+                if variants.is_empty() {
+                    return Err(syn::Error::new_spanned(ident, "enums must have at least one variant"));
+                }
+                let type_norm = NormAttr::from_syn(attrs)?;
+                let tag_size = tag_size_attr(attrs)?.unwrap_or(4);
+                let max_tag = u32::MAX >> (32 - 8 * tag_size);
+                let mut v: Vec<GpuVariant> = Vec::new();
+                for variant in variants {
+                    let vname = variant.ident.to_string();
+                    // Tags follow Rust's rules for discriminants.
+                    let tag = match &variant.discriminant {
+                        Some((_, expr)) => match expr_int_lit(expr) {
+                            Some(tag) if tag <= max_tag as usize => tag as u32,
+                            Some(_) => {
+                                return Err(syn::Error::new_spanned(
+                                    expr,
+                                    format!("tag doesn't fit in `{}`", tag_type_name(tag_size)),
+                                ))
+                            }
+                            None => {
+                                return Err(syn::Error::new_spanned(expr, "expected an integer tag"))
+                            }
+                        },
+                        None => match v.last() {
+                            Some(prev) if prev.tag == max_tag => {
+                                return Err(syn::Error::new_spanned(
+                                    &variant.ident,
+                                    format!("tag doesn't fit in `{}`", tag_type_name(tag_size)),
+                                ))
+                            }
+                            Some(prev) => prev.tag + 1,
+                            None => 0,
+                        },
+                    };
+                    if let Some(prev) = v.iter().find(|prev| prev.tag == tag) {
+                        return Err(syn::Error::new_spanned(
+                            &variant.ident,
+                            format!("tag {} is already used by `{}`", tag, prev.name),
+                        ));
+                    }
+                    let mut fields = Vec::new();
+                    let kind = match &variant.fields {
+                        Fields::Unnamed(FieldsUnnamed { unnamed, .. }) => {
+                            for (ix, field) in unnamed.iter().enumerate() {
+                                let field_ty =
+                                    GpuType::from_syn_field(field, type_norm.clone(), names)?;
+                                fields.push((format!("f{}", ix), field_ty));
+                            }
+                            VariantKind::Tuple
+                        }
+                        Fields::Named(FieldsNamed { named, .. }) => {
+                            for field in named {
+                                let field_ty =
+                                    GpuType::from_syn_field(field, type_norm.clone(), names)?;
+                                push_named_field(&mut fields, field, field_ty)?;
+                            }
+                            VariantKind::Named
+                        }
+                        Fields::Unit => VariantKind::Unit,
+                    };
+                    let wraps_struct = match (kind, &fields[..]) {
+                        (VariantKind::Tuple, [(_, GpuType::InlineStruct(name))]) => {
+                            !names.externs.contains(name)
+                        }
+                        _ => false,
+                    };
+                    v.push(GpuVariant {
+                        name: vname,
+                        kind,
+                        fields,
+                        wraps_struct,
+                        tag,
+                    });
+                }
+                let en = GpuEnum {
+                    name: ident.to_string(),
+                    variants: v,
+                    tag_size,
+                };
+                Ok(GpuTypeDef::Enum(en))
+            }
+            _ => Err(syn::Error::new_spanned(
+                item,
+                "only structs and enums are supported by piet_gpu",
+            )),
+        }
+    }
+}
+
+impl GpuModule {
+    pub(crate) fn from_syn(module: &syn::ItemMod, resolver: &mut Resolver) -> syn::Result<Self> {
+        let mut attrs = HashSet::new();
+        for attr in &module.attrs {
+            if let Some(id) = path_as_single_ident(&attr.path) {
+                attrs.insert(id.to_owned());
+            }
+        }
+        let mode = LayoutAttrValues::from_syn(&module.attrs, &["layout"])?
+            .mode
+            .unwrap_or(LayoutMode::Scalar);
+        let bindings = bindings_attr(&module.attrs)?;
+        let items = match &module.content {
+            Some((_brace, items)) => &items[..],
+            None => &[],
+        };
+        GpuModule::from_items(module.ident.to_string(), attrs, mode, bindings, items, resolver)
+    }
+
+    /// Build a module from its items, adding the definitions of any derived
+    /// or imported types they use.
+    pub(crate) fn from_items(
+        name: String,
+        attrs: HashSet<String>,
+        mode: LayoutMode,
+        bindings: Bindings,
+        items: &[syn::Item],
+        resolver: &mut Resolver,
+    ) -> syn::Result<Self> {
+        let uses = items
+            .iter()
+            .filter_map(|item| match item {
+                syn::Item::Use(item_use) => Some(item_use.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let available = import_types(&uses, resolver)?;
+        let mut defs: Vec<GpuTypeDef> = Vec::new();
+        let mut variant_structs = HashSet::new();
+        let mut type_attrs = HashMap::new();
+        let mut names = ItemNames::new(items, &available);
+        let mut defined = HashSet::new();
+        // The tag size of the enum wrapping each struct, which fixes its layout.
+        let mut wrapped_tag_sizes = HashMap::new();
+        for item in items {
+            if let syn::Item::Use(_) = item {
+                continue;
+            }
+            let def = GpuTypeDef::from_syn(item, &names)?;
+            let item_attrs = match item {
+                syn::Item::Struct(s) => TypeAttrs::from_syn(&s.attrs, Some(&s.fields), mode)?,
+                syn::Item::Enum(e) => TypeAttrs::from_syn(&e.attrs, None, mode)?,
+                _ => unreachable!(),
+            };
+            if let (GpuTypeDef::Enum(en), syn::Item::Enum(syn_enum)) = (&def, item) {
+                for (variant, syn_variant) in en.variants.iter().zip(&syn_enum.variants) {
+                    let variant_attrs = item_attrs.variant(syn_variant)?;
+                    if let Some(wrapped) = variant.wrapped_struct() {
+                        match wrapped_tag_sizes.insert(wrapped.to_string(), en.tag_size) {
+                            Some(tag_size) if tag_size != en.tag_size => {
+                                return Err(syn::Error::new_spanned(
+                                    syn_variant,
+                                    format!(
+                                        "`{}` is already wrapped by an enum with a `{}` tag",
+                                        wrapped,
+                                        tag_type_name(tag_size)
+                                    ),
+                                ))
+                            }
+                            _ => (),
+                        }
+                    }
+                    if variant.wrapped_struct().is_none() && !variant.fields.is_empty() {
+                        let name = format!("{}{}", en.name, variant.name);
+                        if names.all.contains(&name) || !defined.insert(name.clone()) {
+                            return Err(syn::Error::new_spanned(
+                                &syn_variant.ident,
+                                format!(
+                                    "variant needs a struct named `{}`, which is already defined",
+                                    name
+                                ),
+                            ));
+                        }
+                        variant_structs.insert(name.clone());
+                        type_attrs.insert(name.clone(), variant_attrs);
+                        defs.push(GpuTypeDef::Struct(name, variant.fields.clone()));
+                    }
+                }
+            }
+            if !defined.insert(def.name().to_string()) {
+                let ident = match item {
+                    syn::Item::Struct(s) => &s.ident,
+                    syn::Item::Enum(e) => &e.ident,
+                    _ => unreachable!(),
+                };
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    format!("`{}` is defined more than once", def.name()),
+                ));
+            }
+            if let GpuTypeDef::Struct(..) = def {
+                names.defined.insert(def.name().to_string());
+            }
+            type_attrs.insert(def.name().to_string(), item_attrs);
+            defs.push(def);
+        }
+
+        let mut def_modules = defined
+            .iter()
+            .map(|def_name| (def_name.clone(), name.clone()))
+            .collect::<HashMap<_, _>>();
+        // Extern types go first, as they can be stored inline.
+        let mut externs = HashSet::new();
+        let mut extern_defs = Vec::new();
+        for def in &defs {
+            for ty in def.field_types() {
+                let ty_name = match ty.type_name() {
+                    Some(ty_name) if !defined.contains(ty_name) => ty_name,
+                    _ => continue,
+                };
+                let extern_ty = available.iter().find(|ty| ty.name == ty_name).unwrap();
+                for extern_def in &extern_ty.defs {
+                    if externs.insert(extern_def.name().to_string()) {
+                        extern_defs.push(extern_def.clone());
+                    }
+                }
+                variant_structs.extend(extern_ty.variant_structs.iter().cloned());
+                for (name, attrs) in &extern_ty.type_attrs {
+                    type_attrs.insert(name.clone(), attrs.clone());
+                }
+                for (def_name, module) in &extern_ty.def_modules {
+                    def_modules.insert(def_name.clone(), module.clone());
+                }
+            }
+        }
+        extern_defs.extend(defs);
+        let defs = extern_defs;
+
+        let mut enum_variants = HashMap::new();
+        for def in &defs {
+            def.collect_refs(&mut enum_variants);
+        }
+        Ok(GpuModule {
+            name,
+            attrs,
+            enum_variants,
+            variant_structs,
+            externs,
+            uses,
+            def_modules,
+            type_attrs,
+            defs,
+            bindings,
+        })
+    }
+
+    /// The definitions of the type `name`, after those of the types it uses,
+    /// for use by other modules.
+    pub(crate) fn export(&self, name: &str) -> ExternType {
+        let mut used = HashSet::new();
+        let mut pending = vec![name.to_string()];
+        while let Some(ty_name) = pending.pop() {
+            if !used.insert(ty_name.clone()) {
+                continue;
+            }
+            let def = self.resolve_by_name(&ty_name).unwrap();
+            if let GpuTypeDef::Enum(en) = def {
+                pending.extend(en.variants.iter().filter_map(|v| v.struct_name(&en.name)));
+            }
+            // The layout of a struct held by an enum variant depends on the enum.
+            if self.enum_variants.contains_key(&ty_name) {
+                pending.extend(self.defs.iter().filter_map(|def| match def {
+                    GpuTypeDef::Enum(en)
+                        if en.variants.iter().any(|v| v.struct_name(&en.name).as_ref() == Some(&ty_name)) =>
+                    {
+                        Some(en.name.clone())
+                    }
+                    _ => None,
+                }));
+            }
+            for ty in def.field_types() {
+                pending.extend(ty.type_name().map(String::from));
+            }
+        }
+        ExternType {
+            name: name.to_string(),
+            defs: self
+                .defs
+                .iter()
+                .filter(|def| used.contains(def.name()))
+                .cloned()
+                .collect(),
+            variant_structs: self.variant_structs.intersection(&used).cloned().collect(),
+            type_attrs: self
+                .type_attrs
+                .iter()
+                .filter(|(ty_name, _)| used.contains(*ty_name))
+                .map(|(ty_name, attrs)| (ty_name.clone(), attrs.clone()))
+                .collect(),
+            def_modules: self
+                .def_modules
+                .iter()
+                .filter(|(ty_name, _)| used.contains(*ty_name))
+                .map(|(ty_name, module)| (ty_name.clone(), module.clone()))
+                .collect(),
+        }
+    }
+
+    /// The types defined by the module, for other modules to import.
+    pub(crate) fn exported_types(&self) -> Vec<ExternType> {
+        self.defs
+            .iter()
+            .map(|def| def.name())
+            .filter(|name| !self.externs.contains(*name) && !self.variant_structs.contains(*name))
+            .map(|name| self.export(name))
+            .collect()
+    }
+}
+
+/// Look up the types imported by `use` items, which name them by their path
+/// from the crate root, as in `use crate::common::{BBox, SRGBColor};`.
+pub(crate) fn import_types(
+    uses: &[syn::ItemUse],
+    resolver: &mut Resolver,
+) -> syn::Result<Vec<ExternType>> {
+    let mut imported: Vec<ExternType> = Vec::new();
+    for item_use in uses {
+        let mut imports = Vec::new();
+        collect_imports(&item_use.tree, &mut Vec::new(), &mut imports)?;
+        for (path, name) in imports {
+            for ty in resolver.import(&path, name.as_ref())? {
+                if !imported.iter().any(|other| other.name == ty.name) {
+                    imported.push(ty);
+                }
+            }
+        }
+    }
+    Ok(imported)
+}
+
+/// Collect the `(path, type)` pairs named by a `use` tree, with no type for
+/// a glob import.
+pub(crate) fn collect_imports(
+    tree: &syn::UseTree,
+    prefix: &mut Vec<syn::Ident>,
+    imports: &mut Vec<(Vec<syn::Ident>, Option<syn::Ident>)>,
+) -> syn::Result<()> {
+    match tree {
+        syn::UseTree::Path(path) => {
+            prefix.push(path.ident.clone());
+            collect_imports(&path.tree, prefix, imports)?;
+            prefix.pop();
+        }
+        syn::UseTree::Name(name) if !prefix.is_empty() => {
+            imports.push((prefix.clone(), Some(name.ident.clone())))
+        }
+        syn::UseTree::Glob(_) if !prefix.is_empty() => imports.push((prefix.clone(), None)),
+        syn::UseTree::Group(group) => {
+            for tree in &group.items {
+                collect_imports(tree, prefix, imports)?;
+            }
+        }
+        syn::UseTree::Rename(rename) => {
+            return Err(syn::Error::new_spanned(rename, "imported types can't be renamed"))
+        }
+        _ => {
+            return Err(syn::Error::new_spanned(
+                tree,
+                "expected a type of another piet_gpu module or a derived type, as in \
+                 `use crate::common::BBox;`",
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Parse the `#[uses(..)]` attributes of a derived type, naming the derived
+/// types and types of `piet_gpu!` modules it uses by their paths, as `use`
+/// items.
+pub(crate) fn uses_attr(attrs: &[syn::Attribute]) -> syn::Result<Vec<syn::ItemUse>> {
+    let mut uses = Vec::new();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("uses")) {
+        let nested = match attr.parse_meta()? {
+            Meta::List(MetaList { nested, .. }) => nested,
+            meta => {
+                return Err(syn::Error::new_spanned(meta, "expected `#[uses(..)]`"));
+            }
+        };
+        for meta in nested {
+            match meta {
+                NestedMeta::Meta(Meta::Path(path)) => uses.push(syn::parse_quote!(use #path;)),
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        meta,
+                        "expected the path of a type, as in `#[uses(crate::shapes::Circle)]`",
+                    ))
+                }
+            }
+        }
+    }
+    Ok(uses)
+}
+
+/// Add a named field, which must not share its name with an earlier one.
+pub(crate) fn push_named_field(
+    fields: &mut Vec<(String, GpuType)>,
+    field: &syn::Field,
+    ty: GpuType,
+) -> syn::Result<()> {
+    let ident = field.ident.as_ref().unwrap();
+    let name = ident.to_string();
+    if fields.iter().any(|(other, _)| other == &name) {
+        return Err(syn::Error::new_spanned(
+            ident,
+            format!("field `{}` is already declared", name),
+        ));
+    }
+    fields.push((name, ty));
+    Ok(())
+}
+
+pub(crate) fn path_as_single_ident(path: &syn::Path) -> Option<String> {
+    if path.segments.len() == 1 {
+        let seg = &path.segments[0];
+        if seg.arguments == PathArguments::None {
+            return Some(seg.ident.to_string());
+        }
+    }
+    None
+}
+
+pub(crate) fn ty_as_single_ident(ty: &syn::Type) -> Option<String> {
+    if let syn::Type::Path(TypePath { path, .. }) = ty {
+        path_as_single_ident(path)
+    } else {
+        None
+    }
+}
+
+pub(crate) fn expr_int_lit(e: &Expr) -> Option<usize> {
+    if let Expr::Lit(ExprLit {
+                         lit: Lit::Int(lit_int),
+                         ..
+                     }) = e
+    {
+        lit_int.base10_parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Whether the attributes include `#[derive(PietGpu)]`.
+pub(crate) fn derives_piet_gpu(attrs: &[syn::Attribute]) -> bool {
+    attrs.iter().any(|attr| match attr.parse_meta() {
+        Ok(Meta::List(MetaList { path, nested, .. })) if path.is_ident("derive") => {
+            nested.iter().any(|meta| match meta {
+                NestedMeta::Meta(Meta::Path(path)) => match path.segments.last() {
+                    Some(seg) => seg.ident == "PietGpu",
+                    None => false,
+                },
+                _ => false,
+            })
+        }
+        _ => false,
+    })
+}
@@ -0,0 +1,591 @@
+//! Finding the types that a module uses but doesn't define, by following the
+//! paths naming them through the modules of the crate.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use syn::{Lit, Meta, MetaNameValue};
+
+use crate::parse::{derives_piet_gpu, uses_attr};
+use crate::{to_snake_case, Bindings, ExternType, GpuModule, LayoutMode};
+
+/// The number of re-exports followed for one path, which stops a cycle of them.
+const MAX_REEXPORTS: usize = 16;
+
+/// The items of a Rust module in one file.
+struct Items {
+    /// The file holding the items, if they were read from one.
+    file: Option<PathBuf>,
+    /// The directory of the files of the modules declared by `mod name;`.
+    dir: Option<PathBuf>,
+    /// The directory that `#[path]` attributes on those are relative to.
+    path_dir: Option<PathBuf>,
+    items: Vec<syn::Item>,
+}
+
+/// A Rust module. The crate root of a `Schema` is in several parts, one for
+/// each source added.
+#[derive(Clone)]
+struct RustModule {
+    /// The path of the module, as in `crate::common`.
+    path: String,
+    parts: Vec<Rc<Items>>,
+}
+
+/// What a path names.
+enum Found {
+    /// A Rust module, after the modules holding it.
+    Module(Vec<RustModule>),
+    /// A `piet_gpu!` module, with its path and file.
+    GpuModule(String, Option<PathBuf>, Box<syn::ItemMod>),
+    /// A type of a `piet_gpu!` module.
+    GpuType(String, Option<PathBuf>, Box<syn::ItemMod>, syn::Ident),
+    /// A type deriving `PietGpu`, with its path and file.
+    Derived(String, Option<PathBuf>, Box<syn::Item>),
+}
+
+/// Follows the paths of `use` items and `#[uses(..)]` attributes to the
+/// `piet_gpu!` modules and `#[derive(PietGpu)]` types they name, and builds
+/// those when first used.
+pub(crate) struct Resolver {
+    root: RustModule,
+    /// The root file of the crate, which is read when first needed.
+    root_file: Option<Result<PathBuf, String>>,
+    /// The files read, which the generated code depends on.
+    files: Vec<(PathBuf, Rc<Items>)>,
+    module_types: HashMap<String, Vec<ExternType>>,
+    derived_types: HashMap<String, ExternType>,
+    /// The types being built, to report those that use each other.
+    building: Vec<String>,
+}
+
+impl Default for Resolver {
+    fn default() -> Resolver {
+        Resolver {
+            root: RustModule {
+                path: "crate".into(),
+                parts: Vec::new(),
+            },
+            root_file: None,
+            files: Vec::new(),
+            module_types: HashMap::new(),
+            derived_types: HashMap::new(),
+            building: Vec::new(),
+        }
+    }
+}
+
+impl Resolver {
+    /// The crate being compiled, for the proc macros.
+    pub(crate) fn for_crate() -> Resolver {
+        Resolver {
+            root_file: Some(crate_root()),
+            ..Resolver::default()
+        }
+    }
+
+    /// Add items to the crate root, from `file` if it's known.
+    pub(crate) fn add_root(&mut self, items: &[syn::Item], file: Option<&Path>) {
+        let dir = file.and_then(Path::parent).map(Path::to_owned);
+        self.root.parts.push(Rc::new(Items {
+            file: file.map(Path::to_owned),
+            dir: dir.clone(),
+            path_dir: dir,
+            items: items.to_vec(),
+        }));
+    }
+
+    /// The files read to find the types used.
+    pub(crate) fn files(&self) -> impl Iterator<Item = &Path> {
+        self.files.iter().map(|(path, _)| path.as_path())
+    }
+
+    /// Generate items including the files read, so that the crate is rebuilt
+    /// when they change.
+    pub(crate) fn gen_dependencies(&self) -> proc_macro2::TokenStream {
+        let paths = self.files().filter_map(Path::to_str);
+        quote! {
+            #(const _: &[u8] = include_bytes!(#paths);)*
+        }
+    }
+
+    /// The types imported by `use path::name`, or `use path::*` with no name.
+    pub(crate) fn import(
+        &mut self,
+        path: &[syn::Ident],
+        name: Option<&syn::Ident>,
+    ) -> syn::Result<Vec<ExternType>> {
+        if path[0] != "crate" {
+            return Err(syn::Error::new_spanned(
+                &path[0],
+                "types are imported by their path from the crate root, as in \
+                 `use crate::common::BBox;`",
+            ));
+        }
+        let name = match name {
+            Some(name) => name,
+            None => {
+                let span = path.last().unwrap();
+                return match self.follow(&[], path, false, 0)? {
+                    Found::GpuModule(module_path, file, module) => {
+                        self.module_types(&module_path, span, file.as_deref(), &module)
+                    }
+                    Found::Module(stack) => {
+                        let module = stack.last().unwrap();
+                        let mut types = Vec::new();
+                        for part in &module.parts {
+                            for item in &part.items {
+                                if let Some(ident) = derived_ident(item) {
+                                    let ty_path = format!("{}::{}", module.path, ident);
+                                    let file = part.file.as_deref();
+                                    types.push(self.derived(&ty_path, span, file, item)?);
+                                }
+                            }
+                        }
+                        Ok(types)
+                    }
+                    _ => Err(syn::Error::new_spanned(span, format!("`{}` is a type", span))),
+                };
+            }
+        };
+        let mut ty_path = path.to_vec();
+        ty_path.push(name.clone());
+        match self.follow(&[], &ty_path, true, 0)? {
+            Found::Derived(derived_path, file, item) => {
+                Ok(vec![self.derived(&derived_path, name, file.as_deref(), &item)?])
+            }
+            Found::GpuType(module_path, file, module, ty) => {
+                let types = self.module_types(&module_path, name, file.as_deref(), &module)?;
+                match types.into_iter().find(|other| ty == other.name) {
+                    Some(imported) => Ok(vec![imported]),
+                    None => Err(syn::Error::new_spanned(
+                        name,
+                        format!("module `{}` has no type `{}`", module_path, ty),
+                    )),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Follow `path` from the module at the end of `stack`. Its last segment
+    /// names a type if `is_type`, and a module otherwise.
+    fn follow(
+        &mut self,
+        stack: &[RustModule],
+        path: &[syn::Ident],
+        is_type: bool,
+        reexports: usize,
+    ) -> syn::Result<Found> {
+        let (mut found, segments) = match path[0].to_string().as_str() {
+            "crate" => (Found::Module(vec![self.root(&path[0])?]), &path[1..]),
+            "self" => (Found::Module(stack.to_vec()), &path[1..]),
+            "super" if stack.len() > 1 => {
+                (Found::Module(stack[..stack.len() - 1].to_vec()), &path[1..])
+            }
+            "super" => {
+                return Err(syn::Error::new_spanned(&path[0], "the crate root has no `super`"))
+            }
+            _ => (Found::Module(stack.to_vec()), path),
+        };
+        for (ix, segment) in segments.iter().enumerate() {
+            let last = ix + 1 == segments.len();
+            found = match found {
+                Found::Module(stack) => self.find(&stack, segment, last && is_type, reexports)?,
+                Found::GpuModule(module_path, file, module) if last && is_type => {
+                    Found::GpuType(module_path, file, module, segment.clone())
+                }
+                _ => {
+                    let prev = &path[path.len() - segments.len() + ix - 1];
+                    return Err(syn::Error::new_spanned(
+                        segment,
+                        format!("`{}` holds no modules", prev),
+                    ));
+                }
+            };
+        }
+        Ok(found)
+    }
+
+    /// Find the type or module `ident` in the module at the end of `stack`.
+    fn find(
+        &mut self,
+        stack: &[RustModule],
+        ident: &syn::Ident,
+        is_type: bool,
+        reexports: usize,
+    ) -> syn::Result<Found> {
+        let module = stack.last().unwrap().clone();
+        let mut found = Vec::new();
+        for part in &module.parts {
+            for item in &part.items {
+                let item_path = || format!("{}::{}", module.path, ident);
+                match item {
+                    syn::Item::Mod(item_mod) if !is_type && item_mod.ident == *ident => {
+                        let child = self.child_module(&item_path(), part, item_mod, ident)?;
+                        let mut child_stack = stack.to_vec();
+                        child_stack.push(child);
+                        found.push(Found::Module(child_stack));
+                    }
+                    syn::Item::Macro(item_macro)
+                        if !is_type && item_macro.mac.path.is_ident("piet_gpu") =>
+                    {
+                        // Modules that don't parse are reported by their expansion.
+                        match item_macro.mac.parse_body::<syn::ItemMod>() {
+                            Ok(gpu_module) if gpu_module.ident == *ident => {
+                                let (file, module) = (part.file.clone(), Box::new(gpu_module));
+                                found.push(Found::GpuModule(item_path(), file, module))
+                            }
+                            _ => (),
+                        }
+                    }
+                    _ if is_type && derived_ident(item) == Some(ident) => {
+                        let item = Box::new(item.clone());
+                        found.push(Found::Derived(item_path(), part.file.clone(), item))
+                    }
+                    _ => (),
+                }
+            }
+        }
+        if found.len() > 1 {
+            return Err(syn::Error::new_spanned(
+                ident,
+                format!(
+                    "more than one `{}` in `{}`, and piet_gpu can't tell which `#[cfg]` applies",
+                    ident, module.path
+                ),
+            ));
+        }
+        if let Some(found) = found.pop() {
+            return Ok(found);
+        }
+        // Follow re-exports, as in `pub use self::common::BBox;`.
+        let mut named = Vec::new();
+        let mut globs = Vec::new();
+        for part in &module.parts {
+            for item in &part.items {
+                if let syn::Item::Use(syn::ItemUse { leading_colon: None, tree, .. }) = item {
+                    use_paths(tree, &mut Vec::new(), ident, &mut named, &mut globs);
+                }
+            }
+        }
+        if !named.is_empty() || !globs.is_empty() {
+            if reexports == MAX_REEXPORTS {
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    format!("too many re-exports of `{}`", ident),
+                ));
+            }
+            if let Some(path) = named.first() {
+                return self.follow(stack, path, is_type, reexports + 1);
+            }
+            for mut path in globs {
+                path.push(ident.clone());
+                if let Ok(found) = self.follow(stack, &path, is_type, reexports + 1) {
+                    return Ok(found);
+                }
+            }
+        }
+        let files = module
+            .parts
+            .iter()
+            .filter_map(|part| part.file.as_ref())
+            .map(|file| file.display().to_string())
+            .collect::<Vec<_>>();
+        let msg = format!("no `{}` in `{}`", ident, module.path);
+        Err(syn::Error::new_spanned(
+            ident,
+            match files.is_empty() {
+                true => msg,
+                false => format!("{} (searched {})", msg, files.join(", ")),
+            },
+        ))
+    }
+
+    /// The module declared by `item_mod`, an item of `part`.
+    fn child_module(
+        &mut self,
+        path: &str,
+        part: &Items,
+        item_mod: &syn::ItemMod,
+        span: &syn::Ident,
+    ) -> syn::Result<RustModule> {
+        let name = item_mod.ident.to_string();
+        if let Some((_, items)) = &item_mod.content {
+            let dir = part.dir.as_ref().map(|dir| dir.join(&name));
+            return Ok(RustModule {
+                path: path.to_string(),
+                parts: vec![Rc::new(Items {
+                    file: part.file.clone(),
+                    dir: dir.clone(),
+                    path_dir: dir,
+                    items: items.clone(),
+                })],
+            });
+        }
+        let in_file = match &part.file {
+            Some(file) => format!(" in {}", file.display()),
+            None => String::new(),
+        };
+        let file = match (path_attr(&item_mod.attrs), &part.path_dir) {
+            (Some(attr_path), Some(path_dir)) => {
+                let file = path_dir.join(&attr_path);
+                if !file.is_file() {
+                    return Err(syn::Error::new_spanned(
+                        span,
+                        format!(
+                            "can't follow `#[path = {:?}]` of `mod {}`{}: there is no {}",
+                            attr_path,
+                            name,
+                            in_file,
+                            file.display()
+                        ),
+                    ));
+                }
+                file
+            }
+            (_, Some(_)) => {
+                let dir = part.dir.as_ref().unwrap();
+                let candidates = [dir.join(format!("{}.rs", name)), dir.join(&name).join("mod.rs")];
+                match candidates.iter().find(|file| file.is_file()) {
+                    Some(file) => file.clone(),
+                    None => {
+                        return Err(syn::Error::new_spanned(
+                            span,
+                            format!(
+                                "can't find the file of `mod {};`{}: there is no {} or {}",
+                                name,
+                                in_file,
+                                candidates[0].display(),
+                                candidates[1].display()
+                            ),
+                        ))
+                    }
+                }
+            }
+            (_, None) => {
+                return Err(syn::Error::new_spanned(
+                    span,
+                    format!("can't follow `mod {};` in a source added without its file", name),
+                ))
+            }
+        };
+        // Files named by `#[path]` hold their modules' files as `mod.rs` does.
+        let mod_rs =
+            file.file_name() == Some("mod.rs".as_ref()) || path_attr(&item_mod.attrs).is_some();
+        let file_dir = file.parent().unwrap().to_owned();
+        let dir = match mod_rs {
+            true => file_dir.clone(),
+            false => file_dir.join(&name),
+        };
+        Ok(RustModule {
+            path: path.to_string(),
+            parts: vec![self.read_file(&file, dir, file_dir, span)?],
+        })
+    }
+
+    /// The crate root, which is read when first used.
+    fn root(&mut self, span: &syn::Ident) -> syn::Result<RustModule> {
+        match self.root_file.take() {
+            Some(Ok(file)) => {
+                let dir = file.parent().unwrap().to_owned();
+                let items = self.read_file(&file, dir.clone(), dir, span)?;
+                self.root.parts.push(items);
+            }
+            Some(Err(e)) => {
+                self.root_file = Some(Err(e.clone()));
+                return Err(syn::Error::new_spanned(span, e));
+            }
+            None => (),
+        }
+        Ok(self.root.clone())
+    }
+
+    fn read_file(
+        &mut self,
+        file: &Path,
+        dir: PathBuf,
+        path_dir: PathBuf,
+        span: &syn::Ident,
+    ) -> syn::Result<Rc<Items>> {
+        if let Some((_, items)) = self.files.iter().find(|(other, _)| other == file) {
+            return Ok(items.clone());
+        }
+        let src = std::fs::read_to_string(file)
+            .map_err(|e| syn::Error::new_spanned(span, format!("{}: {}", file.display(), e)))?;
+        let parsed = syn::parse_file(&src).map_err(|e| {
+            let start = e.span().start();
+            let msg = format!("{}:{}:{}: {}", file.display(), start.line, start.column + 1, e);
+            syn::Error::new_spanned(span, msg)
+        })?;
+        let items = Rc::new(Items {
+            file: Some(file.to_owned()),
+            dir: Some(dir),
+            path_dir: Some(path_dir),
+            items: parsed.items,
+        });
+        self.files.push((file.to_owned(), items.clone()));
+        Ok(items)
+    }
+
+    fn module_types(
+        &mut self,
+        path: &str,
+        span: &syn::Ident,
+        file: Option<&Path>,
+        module: &syn::ItemMod,
+    ) -> syn::Result<Vec<ExternType>> {
+        if let Some(types) = self.module_types.get(path) {
+            return Ok(types.clone());
+        }
+        let built = self.build(path, span, file, |resolver| GpuModule::from_syn(module, resolver))?;
+        let types = built.exported_types();
+        self.module_types.insert(path.to_string(), types.clone());
+        Ok(types)
+    }
+
+    fn derived(
+        &mut self,
+        path: &str,
+        span: &syn::Ident,
+        file: Option<&Path>,
+        item: &syn::Item,
+    ) -> syn::Result<ExternType> {
+        if let Some(derived) = self.derived_types.get(path) {
+            return Ok(derived.clone());
+        }
+        let module = self.build(path, span, file, |resolver| derived_module(item, resolver))?;
+        let derived = module.export(&derived_ident(item).unwrap().to_string());
+        self.derived_types.insert(path.to_string(), derived.clone());
+        Ok(derived)
+    }
+
+    /// Build a module that `span` uses, reporting its errors at `span`.
+    fn build(
+        &mut self,
+        path: &str,
+        span: &syn::Ident,
+        file: Option<&Path>,
+        build: impl FnOnce(&mut Resolver) -> syn::Result<GpuModule>,
+    ) -> syn::Result<GpuModule> {
+        if let Some(ix) = self.building.iter().position(|other| other == path) {
+            let mut cycle = self.building[ix..].to_vec();
+            cycle.push(path.to_string());
+            return Err(syn::Error::new_spanned(
+                span,
+                format!("piet_gpu types can't use each other: {}", cycle.join(" -> ")),
+            ));
+        }
+        self.building.push(path.to_string());
+        let result = build(self);
+        self.building.pop();
+        result.map_err(|e| {
+            let origin = match file {
+                Some(file) => format!("`{}` in {}", path, file.display()),
+                None => format!("`{}`", path),
+            };
+            syn::Error::new_spanned(span, format!("{}: {}", origin, e))
+        })
+    }
+}
+
+/// The module holding a derived type and the types it uses.
+pub(crate) fn derived_module(item: &syn::Item, resolver: &mut Resolver) -> syn::Result<GpuModule> {
+    let (ident, attrs) = match item {
+        syn::Item::Struct(s) => (&s.ident, &s.attrs),
+        syn::Item::Enum(e) => (&e.ident, &e.attrs),
+        _ => unreachable!(),
+    };
+    let mut items = uses_attr(attrs)?.into_iter().map(syn::Item::Use).collect::<Vec<_>>();
+    items.push(item.clone());
+    GpuModule::from_items(
+        to_snake_case(&ident.to_string()),
+        Default::default(),
+        LayoutMode::Scalar,
+        Bindings::default(),
+        &items,
+        resolver,
+    )
+}
+
+/// The name of a struct or enum deriving `PietGpu`.
+fn derived_ident(item: &syn::Item) -> Option<&syn::Ident> {
+    match item {
+        syn::Item::Struct(s) if derives_piet_gpu(&s.attrs) => Some(&s.ident),
+        syn::Item::Enum(e) if derives_piet_gpu(&e.attrs) => Some(&e.ident),
+        _ => None,
+    }
+}
+
+/// Collect the paths of a `use` tree that bring `ident` into scope, and the
+/// paths of its glob imports.
+fn use_paths(
+    tree: &syn::UseTree,
+    prefix: &mut Vec<syn::Ident>,
+    ident: &syn::Ident,
+    named: &mut Vec<Vec<syn::Ident>>,
+    globs: &mut Vec<Vec<syn::Ident>>,
+) {
+    match tree {
+        syn::UseTree::Path(path) => {
+            prefix.push(path.ident.clone());
+            use_paths(&path.tree, prefix, ident, named, globs);
+            prefix.pop();
+        }
+        syn::UseTree::Name(name) if name.ident == *ident => {
+            named.push(prefix.iter().chain(Some(&name.ident)).cloned().collect())
+        }
+        syn::UseTree::Rename(rename) if rename.rename == *ident => {
+            named.push(prefix.iter().chain(Some(&rename.ident)).cloned().collect())
+        }
+        syn::UseTree::Glob(_) if !prefix.is_empty() => globs.push(prefix.clone()),
+        syn::UseTree::Group(group) => {
+            for tree in &group.items {
+                use_paths(tree, prefix, ident, named, globs);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// The file named by a `#[path = ".."]` attribute.
+fn path_attr(attrs: &[syn::Attribute]) -> Option<String> {
+    attrs.iter().find_map(|attr| match attr.parse_meta() {
+        Ok(Meta::NameValue(MetaNameValue {
+                               path,
+                               lit: Lit::Str(lit),
+                               ..
+                           })) if path.is_ident("path") => Some(lit.value()),
+        _ => None,
+    })
+}
+
+/// The root file of the crate being compiled, from the variables cargo sets.
+fn crate_root() -> Result<PathBuf, String> {
+    let dir = std::env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .ok_or("CARGO_MANIFEST_DIR is not set, so the crate's files can't be found")?;
+    let crate_name = std::env::var("CARGO_CRATE_NAME").unwrap_or_default();
+    let mut candidates = Vec::new();
+    if let Ok(bin) = std::env::var("CARGO_BIN_NAME") {
+        candidates.push(dir.join("src/bin").join(format!("{}.rs", bin)));
+        candidates.push(dir.join("src/bin").join(&bin).join("main.rs"));
+        candidates.push(dir.join("src/main.rs"));
+    } else {
+        let package = std::env::var("CARGO_PKG_NAME").unwrap_or_default();
+        if crate_name == package.replace('-', "_") {
+            candidates.push(dir.join("src/lib.rs"));
+        }
+        // Integration tests, examples and benches are crates of their own.
+        for target_dir in &["tests", "examples", "benches"] {
+            for name in &[crate_name.clone(), crate_name.replace('_', "-")] {
+                candidates.push(dir.join(target_dir).join(format!("{}.rs", name)));
+                candidates.push(dir.join(target_dir).join(name).join("main.rs"));
+            }
+        }
+    }
+    candidates.into_iter().find(|file| file.is_file()).ok_or_else(|| {
+        format!("can't find the root file of crate `{}` in {}", crate_name, dir.display())
+    })
+}
@@ -0,0 +1,1034 @@
+//! Generation of the Rust types, their encoders and views.
+
+use std::ops::Deref;
+
+use crate::layout::{PackedField, PackedStruct};
+use crate::{
+    to_snake_case, GpuEnum, GpuModule, GpuScalar, GpuType, GpuTypeDef, GpuVariant, TargetLang,
+    VariantKind,
+};
+
+impl GpuScalar {
+    pub(crate) fn gen_derive(&self) -> proc_macro2::TokenStream {
+        match self {
+            GpuScalar::F32 => quote!(f32),
+            GpuScalar::I8 => quote!(i8),
+            GpuScalar::I16 => quote!(i16),
+            GpuScalar::I32 => quote!(i32),
+            GpuScalar::U8 => quote!(u8),
+            GpuScalar::U16 => quote!(u16),
+            GpuScalar::U32 => quote!(u32),
+            // `half::f16` or its bits, depending on the `half` feature of the encoder.
+            GpuScalar::F16 => quote!(crate::encoder::F16),
+            // Quantized on encode.
+            GpuScalar::Unorm8 | GpuScalar::Unorm16 | GpuScalar::Snorm8 | GpuScalar::Snorm16 => {
+                quote!(f32)
+            }
+            GpuScalar::Bool => quote!(bool),
+            GpuScalar::UBits(_, 1) => quote!(u8),
+            GpuScalar::UBits(_, 2) => quote!(u16),
+            GpuScalar::UBits(..) => quote!(u32),
+            GpuScalar::IBits(_, 1) => quote!(i8),
+            GpuScalar::IBits(_, 2) => quote!(i16),
+            GpuScalar::IBits(..) => quote!(i32),
+        }
+    }
+
+    /// The Rust type of the stored value.
+    pub(crate) fn gen_storage(&self) -> proc_macro2::TokenStream {
+        match self {
+            GpuScalar::Unorm8 => quote!(u8),
+            GpuScalar::Unorm16 => quote!(u16),
+            GpuScalar::Snorm8 => quote!(i8),
+            GpuScalar::Snorm16 => quote!(i16),
+            _ => self.gen_derive(),
+        }
+    }
+
+    /// Generate an expression for the little-endian bytes of `value`.
+    pub(crate) fn gen_to_bytes(&self, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        match self.norm_scale() {
+            Some(scale) => {
+                let storage = self.gen_storage();
+                let lo = if self.is_snorm() { -1.0f32 } else { 0.0f32 };
+                quote! {
+                    ((#value.max(#lo).min(1.0) * #scale).round() as #storage).to_le_bytes()
+                }
+            }
+            None => quote!(#value.to_le_bytes()),
+        }
+    }
+
+    /// Generate two values whose encodings differ in their lowest bit.
+    pub(crate) fn gen_distinct_values(&self) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+        match self {
+            GpuScalar::F32 => (quote!(f32::from_bits(1)), quote!(0.0)),
+            GpuScalar::F16 => (
+                quote!(crate::encoder::F16::from_le_bytes([1, 0])),
+                quote!(crate::encoder::F16::from_le_bytes([0, 0])),
+            ),
+            GpuScalar::Bool => (quote!(true), quote!(false)),
+            _ if self.norm_scale().is_some() => (quote!(1.0), quote!(0.0)),
+            _ => (quote!(1), quote!(0)),
+        }
+    }
+
+    /// Generate an expression reading a little-endian scalar at `offset` in `buf`.
+    pub(crate) fn gen_decode(&self, offset: usize) -> proc_macro2::TokenStream {
+        let ty = self.gen_storage();
+        let bytes = (offset..offset + self.size()).map(|ix| quote!(buf[#ix]));
+        let value = quote! {
+            #ty::from_le_bytes([#(#bytes),*])
+        };
+        match self.norm_scale() {
+            Some(scale) if self.is_snorm() => quote!((#value as f32 / #scale).max(-1.0)),
+            Some(scale) => quote!(#value as f32 / #scale),
+            None => value,
+        }
+    }
+
+    /// Generate statements replacing the bits of a bit field at `bit_offset`
+    /// in `buf` with `value`, leaving the rest of its word intact.
+    pub(crate) fn gen_encode_bits(&self, bit_offset: usize, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        let word = bit_offset / 32 * 4;
+        let shift = (bit_offset % 32) as u32;
+        let bits = self.bits() as u32;
+        let mask = (1u32 << bits) - 1;
+        quote! {
+            {
+                use crate::encoder::BitField;
+                let word = u32::from_le_bytes([buf[#word], buf[#word + 1], buf[#word + 2], buf[#word + 3]]);
+                let word = (word & !(#mask << #shift)) | (#value.to_bits(#bits) << #shift);
+                buf[#word..#word + 4].copy_from_slice(&word.to_le_bytes());
+            }
+        }
+    }
+
+    /// Generate an expression reading a bit field at `bit_offset` in `buf`.
+    pub(crate) fn gen_decode_bits(&self, bit_offset: usize) -> proc_macro2::TokenStream {
+        let word = bit_offset / 32 * 4;
+        let shift = (bit_offset % 32) as u32;
+        let bits = self.bits() as u32;
+        let ty = self.gen_derive();
+        quote! {
+            <#ty as crate::encoder::BitField>::from_bits(
+                u32::from_le_bytes([buf[#word], buf[#word + 1], buf[#word + 2], buf[#word + 3]]) >> #shift,
+                #bits,
+            )
+        }
+    }
+}
+
+impl PackedField {
+    /// Generate the view methods for this field at byte `offset` in the
+    /// struct, mirroring the shader accessors and unpackers.
+    pub(crate) fn gen_view_accessors(&self, module: &GpuModule, offset: usize) -> proc_macro2::TokenStream {
+        let ty = self.ty.as_ref().unwrap();
+        let name_id = format_ident!("{}", self.name);
+        let mut ts = match ty {
+            GpuType::InlineStruct(name) => {
+                let (view_ty, view) = module.gen_view_at(name, quote!(self.offset + #offset));
+                quote! {
+                    pub fn #name_id(&self) -> #view_ty {
+                        #view
+                    }
+                }
+            }
+            GpuType::Array(elem, len) => {
+                let size = elem.size(module);
+                let (ret_ty, value) = match elem.deref() {
+                    GpuType::InlineStruct(name) => {
+                        module.gen_view_at(name, quote!(self.offset + #offset + ix * #size))
+                    }
+                    _ => {
+                        let decode = elem.gen_decode_field(0, module, false);
+                        (
+                            elem.gen_derive(),
+                            quote! {
+                                let buf = &self.buf[self.offset + #offset + ix * #size..];
+                                #decode
+                            },
+                        )
+                    }
+                };
+                quote! {
+                    pub fn #name_id(&self, ix: usize) -> #ret_ty {
+                        assert!(ix < #len, "array index out of bounds");
+                        #value
+                    }
+                }
+            }
+            _ => {
+                let ret_ty = ty.gen_derive();
+                let decode = ty.gen_decode_field(0, module, false);
+                quote! {
+                    pub fn #name_id(&self) -> #ret_ty {
+                        let buf = &self.buf[self.offset + #offset..];
+                        #decode
+                    }
+                }
+            }
+        };
+        if let GpuType::Slice(elem) = ty {
+            let len_id = format_ident!("{}_len", self.name);
+            let index_id = format_ident!("{}_index", self.name);
+            // As in the shader code, struct elements are returned as refs.
+            let (ret_ty, value) = match elem.deref() {
+                GpuType::InlineStruct(_) => (ty.gen_derive(), quote!(self.#name_id().get(ix))),
+                _ => {
+                    let size = elem.size(module);
+                    let decode = elem.gen_decode_field(0, module, false);
+                    (
+                        elem.gen_derive(),
+                        quote! {
+                            let slice = self.#name_id();
+                            assert!(ix < slice.len(), "slice index out of bounds");
+                            let buf = &self.buf[slice.offset() as usize + ix * #size..];
+                            #decode
+                        },
+                    )
+                }
+            };
+            let ret_ty = match elem.deref() {
+                GpuType::InlineStruct(name) => {
+                    let name_id = format_ident!("{}", name);
+                    quote!(crate::encoder::Ref<#name_id>)
+                }
+                _ => ret_ty,
+            };
+            ts.extend(quote! {
+                pub fn #len_id(&self) -> usize {
+                    self.#name_id().len()
+                }
+
+                pub fn #index_id(&self, ix: usize) -> #ret_ty {
+                    #value
+                }
+            });
+        }
+        if self.is_packed(false) {
+            let packed_ty = ty.gen_derive();
+            let bytes = match ty {
+                GpuType::Vector(_, len) => {
+                    let size = 4 * len;
+                    quote! {
+                        let mut buf = [0u8; #size];
+                        for (i, word) in #name_id.iter().enumerate() {
+                            buf[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+                        }
+                    }
+                }
+                _ => quote!(let buf = #name_id.to_le_bytes();),
+            };
+            for sf in &self.stored_fields {
+                let unpack_id = format_ident!("unpack_{}", sf.name);
+                let sf_ty = sf.ty.gen_derive();
+                let decode = sf.ty.gen_decode_field(sf.offset, module, false);
+                ts.extend(quote! {
+                    pub fn #unpack_id(#name_id: #packed_ty) -> #sf_ty {
+                        #bytes
+                        #decode
+                    }
+                });
+            }
+        }
+        ts
+    }
+}
+
+impl GpuType {
+    /// Generate a Rust type.
+    pub(crate) fn gen_derive(&self) -> proc_macro2::TokenStream {
+        match self {
+            GpuType::Scalar(s) => s.gen_derive(),
+            GpuType::Vector(s, len) => {
+                let scalar = s.gen_derive();
+                quote! { [#scalar; #len] }
+            }
+            GpuType::InlineStruct(name) => {
+                let name_id = format_ident!("{}", name);
+                quote! { #name_id }
+            }
+            GpuType::Ref(ty) => {
+                let gen_ty = ty.gen_derive();
+                quote! { crate::encoder::Ref<#gen_ty> }
+            }
+            GpuType::NullableRef(ty) => {
+                let gen_ty = ty.gen_derive();
+                quote! { Option<crate::encoder::Ref<#gen_ty>> }
+            }
+            GpuType::Slice(ty) => {
+                let gen_ty = ty.gen_derive();
+                quote! { crate::encoder::Slice<#gen_ty> }
+            }
+            GpuType::Array(ty, len) => {
+                let gen_ty = ty.gen_derive();
+                quote! { [#gen_ty; #len] }
+            }
+            GpuType::Affine2 => quote! { crate::encoder::Affine2 },
+        }
+    }
+
+    /// Generate statements encoding `value` at `bit_offset` in `buf`.
+    pub(crate) fn gen_encode_field(
+        &self,
+        module: &GpuModule,
+        bit_offset: usize,
+        value: proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        let offset = bit_offset / 8;
+        match self {
+            GpuType::Scalar(s) if s.is_bitfield() => s.gen_encode_bits(bit_offset, value),
+            GpuType::Scalar(s) => {
+                let end = offset + s.size();
+                let bytes = s.gen_to_bytes(value);
+                quote! {
+                    buf[#offset..#end].copy_from_slice(&#bytes);
+                }
+            }
+            GpuType::Vector(s, len) => {
+                let size = s.size();
+                let bytes = s.gen_to_bytes(quote!(#value[i]));
+                quote! {
+                    for i in 0..#len {
+                        let offset = #offset + i * #size;
+                        buf[offset..offset + #size].copy_from_slice(&#bytes);
+                    }
+                }
+            }
+            GpuType::Ref(_) => {
+                quote! {
+                    buf[#offset..#offset + 4].copy_from_slice(&#value.offset().to_le_bytes());
+                }
+            }
+            GpuType::Array(elem, len) => {
+                let size = elem.size(module);
+                quote! {
+                    for i in 0..#len {
+                        #value[i].encode_to(&mut buf[#offset + i * #size..]);
+                    }
+                }
+            }
+            _ => {
+                quote! {
+                    #value.encode_to(&mut buf[#offset..]);
+                }
+            }
+        }
+    }
+
+    /// Generate two values of this type whose encodings first differ in
+    /// their lowest bit, for finding where a field is encoded.
+    ///
+    /// Structs held by enum variants differ first in the bit after the tag.
+    pub(crate) fn gen_distinct_values(
+        &self,
+        module: &GpuModule,
+    ) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+        match self {
+            GpuType::Scalar(s) => s.gen_distinct_values(),
+            GpuType::Vector(s, len) => {
+                let (a, b) = s.gen_distinct_values();
+                (quote!([#a; #len]), quote!([#b; #len]))
+            }
+            GpuType::Array(elem, len) => {
+                let (a, b) = elem.gen_distinct_values(module);
+                let (a, b) = (vec![a; *len], vec![b; *len]);
+                (quote!([#(#a),*]), quote!([#(#b),*]))
+            }
+            // Refs and slices can only be made by the encoder, so they are decoded.
+            GpuType::Ref(_) => (
+                quote!(crate::encoder::Decode::decode_from(&[1, 0, 0, 0]).unwrap()),
+                quote!(crate::encoder::Decode::decode_from(&[0; 4]).unwrap()),
+            ),
+            GpuType::NullableRef(_) => {
+                (quote!(None), quote!(crate::encoder::Decode::decode_from(&[0; 4]).unwrap()))
+            }
+            GpuType::Slice(_) => (
+                quote!(crate::encoder::Decode::decode_from(&[1, 0, 0, 0, 0, 0, 0, 0]).unwrap()),
+                quote!(crate::encoder::Decode::decode_from(&[0; 8]).unwrap()),
+            ),
+            GpuType::Affine2 => (
+                quote!(crate::encoder::Affine2([f32::from_bits(1); 6])),
+                quote!(crate::encoder::Affine2([0.0; 6])),
+            ),
+            GpuType::InlineStruct(name) => {
+                // Enums can't be stored inline.
+                let fields = match module.resolve_by_name(name).unwrap() {
+                    GpuTypeDef::Struct(_, fields) => fields,
+                    GpuTypeDef::Enum(_) => unreachable!(),
+                };
+                let name_id = format_ident!("{}", name);
+                let field_ids = fields
+                    .iter()
+                    .map(|(field_name, _)| format_ident!("{}", field_name))
+                    .collect::<Vec<_>>();
+                let (a, b): (Vec<_>, Vec<_>) =
+                    fields.iter().map(|(_, ty)| ty.gen_distinct_values(module)).unzip();
+                (
+                    quote!(#name_id { #(#field_ids: #a),* }),
+                    quote!(#name_id { #(#field_ids: #b),* }),
+                )
+            }
+        }
+    }
+
+    /// Generate an expression decoding a value of this type at `bit_offset` in `buf`.
+    ///
+    /// If `fallible`, the expression is in a `decode_from` and passes on the errors
+    /// of the types it decodes. Views read enums by ref, so their fields can't
+    /// fail, and they unwrap them instead.
+    pub(crate) fn gen_decode_field(
+        &self,
+        bit_offset: usize,
+        module: &GpuModule,
+        fallible: bool,
+    ) -> proc_macro2::TokenStream {
+        let offset = bit_offset / 8;
+        match self {
+            GpuType::Scalar(s) if s.is_bitfield() => s.gen_decode_bits(bit_offset),
+            GpuType::Scalar(s) => s.gen_decode(offset),
+            GpuType::Vector(s, len) => {
+                let elems = (0..*len).map(|i| s.gen_decode(offset + i * s.size()));
+                quote! {
+                    [#(#elems),*]
+                }
+            }
+            GpuType::Array(elem, len) => {
+                let size = elem.size(module);
+                let elems = (0..*len)
+                    .map(|i| elem.gen_decode_field((offset + i * size) * 8, module, fallible));
+                quote! {
+                    [#(#elems),*]
+                }
+            }
+            _ => {
+                let gen_ty = self.gen_derive();
+                let decode = quote! {
+                    <#gen_ty as crate::encoder::Decode>::decode_from(&buf[#offset..])
+                };
+                if fallible {
+                    quote!(#decode?)
+                } else {
+                    quote!(#decode.unwrap())
+                }
+            }
+        }
+    }
+}
+
+impl GpuEnum {
+    /// Generate a value of `variant` with the given field values.
+    pub(crate) fn gen_variant(
+        &self,
+        variant: &GpuVariant,
+        values: &[proc_macro2::TokenStream],
+    ) -> proc_macro2::TokenStream {
+        let enum_name = format_ident!("{}", self.name);
+        let variant_id = format_ident!("{}", variant.name);
+        let field_ids = variant.fields.iter().map(|(name, _)| format_ident!("{}", name));
+        match variant.kind {
+            VariantKind::Unit => quote!(#enum_name::#variant_id),
+            VariantKind::Tuple => quote!(#enum_name::#variant_id(#(#values),*)),
+            VariantKind::Named => quote!(#enum_name::#variant_id { #(#field_ids: #values),* }),
+        }
+    }
+
+    /// Generate an expression reading the tag from the start of `buf`.
+    pub(crate) fn gen_decode_tag(&self) -> proc_macro2::TokenStream {
+        let bytes = (0..self.tag_size).map(|i| quote!(buf[#i]));
+        match self.tag_size {
+            1 => quote!(buf[0] as u32),
+            2 => quote!(u16::from_le_bytes([#(#bytes),*]) as u32),
+            _ => quote!(u32::from_le_bytes([#(#bytes),*])),
+        }
+    }
+}
+
+impl GpuTypeDef {
+    /// Generate a struct/enum and encoder impl for the type, and its view.
+    pub(crate) fn gen_derive(&self, module: &GpuModule) -> proc_macro2::TokenStream {
+        let (rust_type, impls) = self.gen_rust(module);
+        let view = self.gen_view(module);
+        quote! {
+            #rust_type
+            #impls
+            #view
+        }
+    }
+
+    /// Generate a read-only view of the type in an encoded buffer, with
+    /// accessors named after the shader functions.
+    pub(crate) fn gen_view(&self, module: &GpuModule) -> proc_macro2::TokenStream {
+        let name = self.name();
+        let view_id = format_ident!("{}View", name);
+        let mut methods = proc_macro2::TokenStream::new();
+        match self {
+            GpuTypeDef::Struct(_, fields) => {
+                let packed_form = PackedStruct::new(module, name, fields);
+                for (packed_field, offset) in packed_form
+                    .packed_fields
+                    .iter()
+                    .zip(packed_form.packed_field_offsets(module))
+                {
+                    methods.extend(packed_field.gen_view_accessors(module, offset));
+                }
+            }
+            GpuTypeDef::Enum(en) => {
+                let decode_tag = en.gen_decode_tag();
+                methods.extend(quote! {
+                    pub fn tag(&self) -> u32 {
+                        let buf = &self.buf[self.offset..];
+                        #decode_tag
+                    }
+                });
+                for variant in &en.variants {
+                    let struct_name = match variant.struct_name(&en.name) {
+                        Some(struct_name) => struct_name,
+                        None => continue,
+                    };
+                    let variant_id = format_ident!("as_{}", to_snake_case(&variant.name));
+                    let tag = variant.tag;
+                    let (view_ty, view) = module.gen_view_at(&struct_name, quote!(self.offset));
+                    let doc = format!(" The `{}` variant, if the tag is `{}_{}`.", variant.name, en.name, variant.name);
+                    methods.extend(quote! {
+                        #[doc = #doc]
+                        pub fn #variant_id(&self) -> Option<#view_ty> {
+                            if self.tag() == #tag {
+                                Some(#view)
+                            } else {
+                                None
+                            }
+                        }
+                    });
+                }
+            }
+        }
+        let doc = format!(" A view of a `{}` in an encoded buffer, read in place.", name);
+        let view_doc = format!(" A view of the `{}` at `offset` bytes into `buf`.", name);
+        let mut ts = quote! {
+            #[doc = #doc]
+            #[derive(Clone, Copy)]
+            pub struct #view_id<'a> {
+                buf: &'a [u8],
+                offset: usize,
+            }
+
+            impl<'a> #view_id<'a> {
+                #[doc = #view_doc]
+                pub fn from_offset(buf: &'a [u8], offset: u32) -> Self {
+                    #view_id {
+                        buf,
+                        offset: offset as usize,
+                    }
+                }
+
+                #methods
+            }
+        };
+        // Variant structs have no Rust type to refer to them.
+        if !module.variant_structs.contains(name) {
+            let name_id = format_ident!("{}", name);
+            ts.extend(quote! {
+                impl<'a> #view_id<'a> {
+                    /// A view of the object referenced by `r`.
+                    pub fn new(buf: &'a [u8], r: crate::encoder::Ref<#name_id>) -> Self {
+                        #view_id::from_offset(buf, r.offset())
+                    }
+                }
+
+                impl<'a> crate::encoder::Viewable<'a> for #name_id {
+                    type View = #view_id<'a>;
+
+                    fn view_at(buf: &'a [u8], offset: u32) -> #view_id<'a> {
+                        #view_id::from_offset(buf, offset)
+                    }
+                }
+            });
+        }
+        ts
+    }
+
+    /// Generate the Rust type, and separately its encoder impls.
+    pub(crate) fn gen_rust(
+        &self,
+        module: &GpuModule,
+    ) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+        match self {
+            GpuTypeDef::Struct(name, fields) => {
+                // The fields of these are held by the enum variant itself.
+                if module.variant_structs.contains(name) {
+                    return (proc_macro2::TokenStream::new(), proc_macro2::TokenStream::new());
+                }
+                let name_id = format_ident!("{}", name);
+                let mut gen_fields = proc_macro2::TokenStream::new();
+                for (field_name, ty) in fields {
+                    let field_name_id = format_ident!("{}", field_name);
+                    let gen_ty = ty.gen_derive();
+                    let gen_field = quote! {
+                        pub #field_name_id: #gen_ty,
+                    };
+                    gen_fields.extend(gen_field);
+                }
+                let encoded_size = self.size(module);
+                let alignment = self.alignment(module);
+                // The offsets are those of the packed struct read by the shader code.
+                let offsets = PackedStruct::new(module, name, fields).field_bit_offsets(module);
+
+                let mut encode_fields = proc_macro2::TokenStream::new();
+                let mut decode_fields = proc_macro2::TokenStream::new();
+                for ((field_name, ty), (_, offset)) in fields.iter().zip(offsets) {
+                    let field_name_id = format_ident!("{}", field_name);
+                    let encode_field =
+                        ty.gen_encode_field(module, offset, quote!(self.#field_name_id));
+                    let decode_field = ty.gen_decode_field(offset, module, true);
+                    decode_fields.extend(quote! {
+                        #field_name_id: #decode_field,
+                    });
+                    encode_fields.extend(encode_field);
+                }
+
+                let rust_type = quote! {
+                    #[derive(Clone, Debug, PartialEq)]
+                    pub struct #name_id {
+                        #gen_fields
+                    }
+                };
+                let impls = quote! {
+                    impl crate::encoder::Encode for #name_id {
+                        fn fixed_size() -> usize {
+                            #encoded_size
+                        }
+                        fn alignment() -> usize {
+                            #alignment
+                        }
+                        fn encode_to(&self, buf: &mut [u8]) {
+                            #encode_fields
+                        }
+                    }
+
+                    impl crate::encoder::Decode for #name_id {
+                        fn decode_from(buf: &[u8]) -> Result<Self, crate::encoder::DecodeError> {
+                            Ok(#name_id {
+                                #decode_fields
+                            })
+                        }
+                    }
+                };
+                (rust_type, impls)
+            }
+            GpuTypeDef::Enum(en) => {
+                let enum_name = format_ident!("{}", en.name);
+                let mut variants = proc_macro2::TokenStream::new();
+                let mut cases = proc_macro2::TokenStream::new();
+                let mut decode_cases = proc_macro2::TokenStream::new();
+                let mut tag_consts = proc_macro2::TokenStream::new();
+                let tag_size = en.tag_size;
+                for variant in &en.variants {
+                    let tag = variant.tag;
+                    let variant_id = format_ident!("{}", variant.name);
+                    let field_ids = variant
+                        .fields
+                        .iter()
+                        .map(|(name, _)| format_ident!("{}", name))
+                        .collect::<Vec<_>>();
+                    let field_tys = variant
+                        .fields
+                        .iter()
+                        .map(|(_, field)| field.gen_derive());
+                    let variant_def = match variant.kind {
+                        VariantKind::Unit => quote! { #variant_id, },
+                        VariantKind::Tuple => quote! { #variant_id(#(#field_tys),*), },
+                        VariantKind::Named => {
+                            quote! { #variant_id { #(#field_ids: #field_tys),* }, }
+                        }
+                    };
+                    variants.extend(variant_def);
+                    // A wrapped struct holds the tag, so it starts at the beginning; other
+                    // fields are laid out by the generated variant struct.
+                    let offsets = if variant.wrapped_struct().is_some() {
+                        vec![0]
+                    } else if variant.fields.is_empty() {
+                        vec![]
+                    } else {
+                        PackedStruct::new(module, &variant.struct_name(&en.name).unwrap(), &variant.fields)
+                            .field_bit_offsets(module)
+                            .into_iter()
+                            .map(|(_, offset)| offset)
+                            .collect()
+                    };
+                    let mut field_encoders = proc_macro2::TokenStream::new();
+                    let mut field_decoders = Vec::new();
+                    for (((_, field), field_id), offset) in
+                        variant.fields.iter().zip(&field_ids).zip(offsets)
+                    {
+                        let field_encoder = field.gen_encode_field(module, offset, quote!(#field_id));
+                        field_encoders.extend(field_encoder);
+                        field_decoders.push(field.gen_decode_field(offset, module, true));
+                    }
+                    let (pattern, constructor) = match variant.kind {
+                        VariantKind::Unit => {
+                            (quote!(#enum_name::#variant_id), quote!(#enum_name::#variant_id))
+                        }
+                        VariantKind::Tuple => (
+                            quote!(#enum_name::#variant_id(#(#field_ids),*)),
+                            quote!(#enum_name::#variant_id(#(#field_decoders),*)),
+                        ),
+                        VariantKind::Named => (
+                            quote!(#enum_name::#variant_id { #(#field_ids),* }),
+                            quote!(#enum_name::#variant_id { #(#field_ids: #field_decoders),* }),
+                        ),
+                    };
+                    let case = quote! {
+                        #pattern => {
+                            buf[0..#tag_size].copy_from_slice(&#tag.to_le_bytes()[0..#tag_size]);
+                            #field_encoders
+                        }
+                    };
+                    cases.extend(case);
+                    decode_cases.extend(quote! {
+                        #tag => Ok(#constructor),
+                    });
+                    let const_id =
+                        format_ident!("{}_TAG", to_snake_case(&variant.name).to_uppercase());
+                    let doc = format!(" The tag of `{}::{}`.", en.name, variant.name);
+                    tag_consts.extend(quote! {
+                        #[doc = #doc]
+                        pub const #const_id: u32 = #tag;
+                    });
+                }
+                let decode_tag = en.gen_decode_tag();
+                let encoded_size = self.size(module);
+                let alignment = self.alignment(module);
+                let rust_type = quote! {
+                    #[derive(Clone, Debug, PartialEq)]
+                    pub enum #enum_name {
+                        #variants
+                    }
+                };
+                let impls = quote! {
+                    impl crate::encoder::Encode for #enum_name {
+                        fn fixed_size() -> usize {
+                            #encoded_size
+                        }
+                        fn alignment() -> usize {
+                            #alignment
+                        }
+                        fn encode_to(&self, buf: &mut [u8]) {
+                            match self {
+                                #cases
+                            }
+                        }
+                    }
+
+                    impl crate::encoder::Decode for #enum_name {
+                        fn decode_from(buf: &[u8]) -> Result<Self, crate::encoder::DecodeError> {
+                            let tag = #decode_tag;
+                            match tag {
+                                #decode_cases
+                                _ => Err(crate::encoder::DecodeError::UnknownTag {
+                                    ty: stringify!(#enum_name),
+                                    tag,
+                                }),
+                            }
+                        }
+                    }
+
+                    #[allow(dead_code)]
+                    impl #enum_name {
+                        #tag_consts
+                    }
+                };
+                (rust_type, impls)
+            }
+        }
+    }
+}
+
+impl GpuModule {
+    /// Generate the view type of a struct, and an expression for a view of
+    /// it at byte `offset` in the buffer of `self`.
+    ///
+    /// Views of extern types are named through `Viewable`, as they may not
+    /// be in scope.
+    pub(crate) fn gen_view_at(
+        &self,
+        name: &str,
+        offset: proc_macro2::TokenStream,
+    ) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+        if self.externs.contains(name) {
+            let name_id = format_ident!("{}", name);
+            (
+                quote!(<#name_id as crate::encoder::Viewable<'a>>::View),
+                quote!(<#name_id as crate::encoder::Viewable<'a>>::view_at(self.buf, (#offset) as u32)),
+            )
+        } else {
+            let view_id = format_ident!("{}View", name);
+            (
+                quote!(#view_id<'a>),
+                quote!(#view_id::from_offset(self.buf, (#offset) as u32)),
+            )
+        }
+    }
+
+    /// Generate a match on `lang` returning the shader code for that language.
+    pub(crate) fn gen_shader_match(&self) -> proc_macro2::TokenStream {
+        let hlsl_result = self.to_shader(TargetLang::Hlsl);
+        let msl_result = self.to_shader(TargetLang::Msl);
+        let glsl_result = self.to_shader(TargetLang::Glsl);
+        let wgsl_result = self.to_shader(TargetLang::Wgsl);
+        quote! {
+            match lang {
+                "HLSL" => #hlsl_result.into(),
+                "MSL" => #msl_result.into(),
+                "GLSL" => #glsl_result.into(),
+                "WGSL" => #wgsl_result.into(),
+                _ => panic!("unknown shader lang {}", lang),
+            }
+        }
+    }
+
+    /// Generate an expression for the `crate::layout::ModuleLayout` of the module.
+    pub(crate) fn gen_layout(&self) -> proc_macro2::TokenStream {
+        let mut types = Vec::new();
+        for def in &self.defs {
+            let name = def.name();
+            let size = def.size(self);
+            let alignment = def.alignment(self);
+            let kind = match def {
+                GpuTypeDef::Struct(name, fields) => {
+                    let packed_form = PackedStruct::new(self, name, fields);
+                    let has_tag = packed_form.is_enum_variant;
+                    let mut packed_fields = Vec::new();
+                    for (packed_field, offset) in packed_form
+                        .packed_fields
+                        .iter()
+                        .zip(packed_form.packed_field_offsets(self))
+                    {
+                        let pf_name = &packed_field.name;
+                        let pf_size = packed_field.size(self).unwrap();
+                        let fields = packed_field.stored_fields.iter().map(|sf| {
+                            let sf_name = &sf.name;
+                            let ty = sf.ty.schema_name();
+                            let bit_offset = sf.offset;
+                            let size = sf.ty.size(self);
+                            let bit_size = sf.ty.bits(self);
+                            quote! {
+                                crate::layout::FieldLayout {
+                                    name: #sf_name,
+                                    ty: #ty,
+                                    bit_offset: #bit_offset,
+                                    size: #size,
+                                    bit_size: #bit_size,
+                                }
+                            }
+                        });
+                        packed_fields.push(quote! {
+                            crate::layout::PackedFieldLayout {
+                                name: #pf_name,
+                                offset: #offset,
+                                size: #pf_size,
+                                fields: vec![#(#fields),*],
+                            }
+                        });
+                    }
+                    quote! {
+                        crate::layout::TypeKind::Struct {
+                            has_tag: #has_tag,
+                            packed_fields: vec![#(#packed_fields),*],
+                        }
+                    }
+                }
+                GpuTypeDef::Enum(en) => {
+                    let variants = en.variants.iter().map(|variant| {
+                        let variant_name = &variant.name;
+                        let tag = variant.tag;
+                        let (body, body_offset) = match variant.struct_name(&en.name) {
+                            Some(body) => {
+                                let fields = match self.resolve_by_name(&body).unwrap() {
+                                    GpuTypeDef::Struct(_, fields) => fields,
+                                    _ => unreachable!(),
+                                };
+                                let packed_form = PackedStruct::new(self, &body, fields);
+                                let offsets = packed_form.field_bit_offsets(self);
+                                let offset = offsets.first().map_or(4, |(_, offset)| offset / 8);
+                                (quote!(Some(#body)), offset)
+                            }
+                            None => (quote!(None), 4),
+                        };
+                        quote! {
+                            crate::layout::VariantLayout {
+                                name: #variant_name,
+                                tag: #tag,
+                                body: #body,
+                                body_offset: #body_offset,
+                            }
+                        }
+                    });
+                    let tag_size = en.tag_size;
+                    quote! {
+                        crate::layout::TypeKind::Enum {
+                            tag_size: #tag_size,
+                            variants: vec![#(#variants),*],
+                        }
+                    }
+                }
+            };
+            types.push(quote! {
+                crate::layout::TypeLayout {
+                    name: #name,
+                    size: #size,
+                    alignment: #alignment,
+                    kind: #kind,
+                }
+            });
+        }
+        let module_name = &self.name;
+        quote! {
+            crate::layout::ModuleLayout {
+                name: #module_name,
+                types: vec![#(#types),*],
+            }
+        }
+    }
+
+    /// Generate `check_layout`, which checks that each field of the Rust
+    /// types is encoded where the layout of the shaders, `layout_<module>()`,
+    /// has it.
+    ///
+    /// The offset of a field is found from the encodings of two values that
+    /// differ only in that field.
+    pub(crate) fn gen_layout_check(&self) -> proc_macro2::TokenStream {
+        let mut checks = Vec::new();
+        for def in &self.defs {
+            match def {
+                GpuTypeDef::Struct(name, fields) if !self.variant_structs.contains(name) => {
+                    let name_id = format_ident!("{}", name);
+                    let field_ids = fields
+                        .iter()
+                        .map(|(field_name, _)| format_ident!("{}", field_name))
+                        .collect::<Vec<_>>();
+                    self.gen_field_checks(name, fields, &mut checks, |values| {
+                        quote!(#name_id { #(#field_ids: #values),* })
+                    });
+                }
+                GpuTypeDef::Enum(en) => {
+                    for variant in &en.variants {
+                        // Wrapped structs are checked themselves.
+                        if variant.wrapped_struct().is_some() || variant.fields.is_empty() {
+                            continue;
+                        }
+                        let name = variant.struct_name(&en.name).unwrap();
+                        self.gen_field_checks(&name, &variant.fields, &mut checks, |values| {
+                            en.gen_variant(variant, values)
+                        });
+                    }
+                }
+                _ => (),
+            }
+        }
+        let layout_fn = format_ident!("layout_{}", self.name);
+        quote! {
+            /// Check that the offsets written by the Rust encoder match those
+            /// read by the shaders.
+            pub fn check_layout() -> Result<(), String> {
+                let layout = super::#layout_fn();
+                let fields: &[(&str, &str, &str, Option<usize>)] = &[#(#checks),*];
+                for &(ty, packed_field, field, bit_offset) in fields {
+                    crate::layout::check_field_offset(
+                        &layout,
+                        ty,
+                        packed_field,
+                        field,
+                        bit_offset,
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Generate a check of each field of the struct `name`, whose values are
+    /// made by `gen_value` from the values of the fields.
+    fn gen_field_checks(
+        &self,
+        name: &str,
+        fields: &Vec<(String, GpuType)>,
+        checks: &mut Vec<proc_macro2::TokenStream>,
+        gen_value: impl Fn(&[proc_macro2::TokenStream]) -> proc_macro2::TokenStream,
+    ) {
+        let values = fields
+            .iter()
+            .map(|(_, ty)| ty.gen_distinct_values(self))
+            .collect::<Vec<_>>();
+        let base = values.iter().map(|(a, _)| a.clone()).collect::<Vec<_>>();
+        let packed_form = PackedStruct::new(self, name, fields);
+        for (ix, (field_name, ty)) in fields.iter().enumerate() {
+            let packed_field = packed_form
+                .packed_fields
+                .iter()
+                .find(|pf| pf.stored_fields.iter().any(|f| &f.name == field_name))
+                .unwrap();
+            let pf_name = &packed_field.name;
+            let mut changed = base.clone();
+            changed[ix] = values[ix].1.clone();
+            let a = gen_value(&base);
+            let b = gen_value(&changed);
+            // The element that an array starts with.
+            let mut first = ty;
+            while let GpuType::Array(elem, _) = first {
+                first = elem;
+            }
+            let bit_offset = match first {
+                // The first bit that differs in a struct may follow its tag.
+                GpuType::InlineStruct(_) => {
+                    let (field_a, field_b) = first.gen_distinct_values(self);
+                    quote! {
+                        crate::layout::encoded_field_offset(&#a, &#b, &#field_a, &#field_b)
+                    }
+                }
+                _ => quote!(crate::layout::encoded_bit_offset(&#a, &#b)),
+            };
+            checks.push(quote!((#name, #pf_name, #field_name, #bit_offset)));
+        }
+    }
+
+    /// Generate the Rust module, with the Rust types under `#[rust_encode]`.
+    pub(crate) fn gen_derive(&self) -> proc_macro2::TokenStream {
+        let module_name = format_ident!("{}", self.name);
+        let schema_hash = self.schema_hash();
+        if !self.attrs.contains("rust_encode") {
+            return quote! {
+                pub mod #module_name {
+                    /// The hash of the layout, as in the shader code.
+                    pub const SCHEMA_HASH: u64 = #schema_hash;
+                }
+            };
+        }
+        let mut ts = proc_macro2::TokenStream::new();
+        for def in &self.defs {
+            if self.externs.contains(def.name()) {
+                continue;
+            }
+            let def_ts = def.gen_derive(self);
+            ts.extend(def_ts);
+        }
+        let uses = &self.uses;
+        let layout_check = self.gen_layout_check();
+        quote! {
+            pub mod #module_name {
+                // For the derived types used by the module.
+                #[allow(unused_imports)]
+                use super::*;
+                #(#uses)*
+
+                /// The hash of the layout, as in the shader code.
+                pub const SCHEMA_HASH: u64 = #schema_hash;
+
+                #ts
+
+                #layout_check
+            }
+        }
+    }
+}
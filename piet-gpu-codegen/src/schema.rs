@@ -0,0 +1,143 @@
+//! Schemas read from source files, for build scripts.
+
+use std::path::{Path, PathBuf};
+
+use syn::{ItemEnum, ItemStruct};
+
+use crate::parse::derives_piet_gpu;
+use crate::resolve::Resolver;
+use crate::shader::combined_shader;
+use crate::{derive_piet_gpu_impl, GpuModule, TargetLang};
+
+/// `piet_gpu!` modules parsed from Rust source, for generating shader code
+/// outside of the proc macro, as in a build script.
+///
+/// The sources added so far make up the crate root, where the paths of the
+/// types that modules import start, so sources must be added after those
+/// they import from. The files declared by `mod name;` in added files are
+/// read as needed.
+#[derive(Default)]
+pub struct Schema {
+    modules: Vec<GpuModule>,
+    paths: Vec<PathBuf>,
+    resolver: Resolver,
+}
+
+impl Schema {
+    pub fn new() -> Schema {
+        Schema::default()
+    }
+
+    /// Add the `piet_gpu!` modules and `#[derive(PietGpu)]` types in a Rust
+    /// source file.
+    pub fn add_file(&mut self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        let src = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.add_parsed(&src, Some(path))
+            .map_err(|e| format!("{}:{}", path.display(), e))?;
+        self.paths.push(path.to_owned());
+        Ok(())
+    }
+
+    /// Add the `piet_gpu!` modules and `#[derive(PietGpu)]` types in Rust source.
+    ///
+    /// Errors are reported with their line and column.
+    pub fn add_source(&mut self, src: &str) -> Result<(), String> {
+        self.add_parsed(src, None)
+    }
+
+    fn add_parsed(&mut self, src: &str, path: Option<&Path>) -> Result<(), String> {
+        syn::parse_file(src)
+            .and_then(|file| {
+                self.resolver.add_root(&file.items, path);
+                self.add_items(&file.items)
+            })
+            .map_err(|e| {
+                let start = e.span().start();
+                format!("{}:{}: {}", start.line, start.column + 1, e)
+            })
+    }
+
+    pub(crate) fn add_items(&mut self, items: &[syn::Item]) -> syn::Result<()> {
+        for item in items {
+            match item {
+                syn::Item::Macro(item_macro) if item_macro.mac.path.is_ident("piet_gpu") => {
+                    let module =
+                        GpuModule::from_syn(&item_macro.mac.parse_body()?, &mut self.resolver)?;
+                    self.modules.retain(|other| other.name != module.name);
+                    self.modules.push(module);
+                }
+                syn::Item::Struct(ItemStruct { attrs, .. }) | syn::Item::Enum(ItemEnum { attrs, .. })
+                    if derives_piet_gpu(attrs) =>
+                {
+                    derive_piet_gpu_impl(item.clone(), &mut self.resolver)?;
+                }
+                syn::Item::Mod(syn::ItemMod {
+                                   content: Some((_, items)),
+                                   ..
+                               }) => self.add_items(items)?,
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// The names of the modules, in the order they were added.
+    pub fn module_names(&self) -> Vec<&str> {
+        self.modules.iter().map(|module| module.name.as_str()).collect()
+    }
+
+    /// The shader code of a module, as returned by its `gen_gpu_<module>`.
+    pub fn gen_shader(&self, module: &str, target: TargetLang) -> Option<String> {
+        self.modules
+            .iter()
+            .find(|m| m.name == module)
+            .map(|m| m.to_shader(target))
+    }
+
+    /// The shader code of several modules, for a kernel including them all.
+    /// Definitions the modules share are only included once, which WGSL needs
+    /// as it has no include guards.
+    pub fn gen_kernel_shader(&self, modules: &[&str], target: TargetLang) -> Option<String> {
+        let modules = modules
+            .iter()
+            .map(|&name| self.modules.iter().find(|m| m.name == name))
+            .collect::<Option<Vec<_>>>()?;
+        Some(combined_shader(&modules, target))
+    }
+
+    /// Write the shader code of a module to `path` with `write_if_changed`.
+    pub fn write_shader(
+        &self,
+        module: &str,
+        target: TargetLang,
+        path: impl AsRef<Path>,
+    ) -> Result<bool, String> {
+        let path = path.as_ref();
+        let shader = self
+            .gen_shader(module, target)
+            .ok_or_else(|| format!("no piet_gpu module `{}`", module))?;
+        write_if_changed(path, &shader).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Print `cargo:rerun-if-changed` for each file added, and each file read
+    /// to find the types they use, from a build script.
+    pub fn rerun_if_changed(&self) {
+        for path in self.paths.iter().map(PathBuf::as_path).chain(self.resolver.files()) {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+}
+
+/// Write `contents` to `path` unless it already holds them, so that the file
+/// is only touched when it changes. Returns whether it was written.
+pub fn write_if_changed(path: impl AsRef<Path>, contents: &str) -> std::io::Result<bool> {
+    let path = path.as_ref();
+    if let Ok(existing) = std::fs::read_to_string(path) {
+        if existing == contents {
+            return Ok(false);
+        }
+    }
+    std::fs::write(path, contents)?;
+    Ok(true)
+}
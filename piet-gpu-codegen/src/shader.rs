@@ -0,0 +1,1775 @@
+//! Generation of the shader code reading and writing the types.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::ops::Deref;
+
+use crate::layout::{size_in_uints, PackedField, PackedStruct, StoredField};
+use crate::{to_snake_case, GpuModule, GpuScalar, GpuType, GpuTypeDef, TargetLang};
+
+impl GpuScalar {
+    /// The unpacked type of the scalar value.
+    pub(crate) fn unpacked_type(self, target: TargetLang) -> GpuScalar {
+        if self.norm_scale().is_some() {
+            return GpuScalar::F32;
+        }
+        match self {
+            GpuScalar::UBits(..) => return GpuScalar::U32,
+            GpuScalar::IBits(..) => return GpuScalar::I32,
+            _ => (),
+        }
+        if target.backend().has_small_scalars() {
+            return self;
+        }
+        match self {
+            GpuScalar::I8 | GpuScalar::I16 => GpuScalar::I32,
+            GpuScalar::U8 | GpuScalar::U16 => GpuScalar::U32,
+            GpuScalar::F16 => GpuScalar::F32,
+            _ => self,
+        }
+    }
+
+    pub(crate) fn typename(self, target: TargetLang) -> &'static str {
+        if self == GpuScalar::Bool {
+            return "bool";
+        }
+        if !target.backend().has_small_scalars() && self.size() < 4 {
+            panic!(
+                "Internal logic error: trying to determine HLSL/GLSL/WGSL typename for {} byte value",
+                self.size()
+            );
+        }
+        target.backend().scalar_typename(self)
+    }
+
+    /// The infix distinguishing the extractor for this scalar, e.g.
+    /// `extract_8bit_signed_value` for `I8`.
+    pub(crate) fn extractor_kind(self) -> &'static str {
+        match self {
+            GpuScalar::I8
+            | GpuScalar::I16
+            | GpuScalar::Snorm8
+            | GpuScalar::Snorm16
+            | GpuScalar::IBits(..) => "signed_",
+            _ => "",
+        }
+    }
+
+    /// Convert an expression with type "uint" into the given scalar.
+    pub(crate) fn cvt(self, inner: &str, target: TargetLang) -> String {
+        self.cvt_vec(inner, 1, target)
+    }
+
+    /// Convert a uint vector into the given vector
+    pub(crate) fn cvt_vec(self, inner: &str, size: usize, target: TargetLang) -> String {
+        if let Some(scale) = self.norm_scale() {
+            let float = target.backend().vector_typename(GpuScalar::F32, size);
+            let value = format!("{}({}) / {:.1}", float, inner, scale);
+            // The most negative snorm value is clamped, so that -1.0 has two encodings.
+            return if self.is_snorm() {
+                format!("max({}, {}(-1.0))", value, float)
+            } else {
+                value
+            };
+        }
+        match self {
+            GpuScalar::Bool => format!("bool({})", inner),
+            // Already extended to 32 bits by their extractor.
+            GpuScalar::UBits(..) | GpuScalar::IBits(..) => inner.into(),
+            _ => target.backend().bits_to_value(self, inner, size),
+        }
+    }
+
+    /// Convert an unpacked value of the given scalar type into uint bits, the
+    /// inverse of `cvt_vec`.
+    pub(crate) fn cvt_to_uint_vec(self, inner: &str, size: usize, target: TargetLang) -> String {
+        let uint_vec = target.backend().vector_typename(GpuScalar::U32, size);
+        if let Some(scale) = self.norm_scale() {
+            let float = target.backend().vector_typename(GpuScalar::F32, size);
+            let lo = if self.is_snorm() { "-1.0" } else { "0.0" };
+            let clamped = format!("clamp({}, {}({}), {}(1.0))", inner, float, lo, float);
+            let rounded = format!("round({} * {:.1})", clamped, scale);
+            // The inserter masks off the high bits of negative values.
+            return if self.is_snorm() {
+                let int = target.backend().vector_typename(GpuScalar::I32, size);
+                format!("{}({}({}))", uint_vec, int, rounded)
+            } else {
+                format!("{}({})", uint_vec, rounded)
+            };
+        }
+        match self {
+            GpuScalar::U32 | GpuScalar::UBits(..) => inner.into(),
+            _ => target.backend().value_to_bits(self, inner, size),
+        }
+    }
+}
+
+/// If `c = 0`, return `"var_name`, else `"var_name + c"`
+pub(crate) fn simplified_add(var_name: &str, c: usize) -> String {
+    if c == 0 {
+        String::from(var_name)
+    } else {
+        format!("{} + {}", var_name, c)
+    }
+}
+
+/// Suffix to add to scalar type to make it into a vector.
+///
+/// For size of 1, returns empty string, though "usize1" is usually valid.
+/// This is so we have one name for the same type, and also so the suffix
+/// can be used for `ByteAddressBuf.Load` method names.
+pub(crate) fn vector_size_str(size: usize) -> &'static str {
+    match size {
+        1 => "",
+        2 => "2",
+        3 => "3",
+        4 => "4",
+        _ => panic!("illegal vector size {}", size),
+    }
+}
+
+/// A struct member of `size` bytes of padding, if any.
+pub(crate) fn padding_field(size: usize, pad_ix: &mut usize, target: TargetLang) -> String {
+    if size == 0 {
+        return String::new();
+    }
+    let name = format!("_pad{}", pad_ix);
+    *pad_ix += 1;
+    target.backend().struct_array_field(target.backend().uint_typename(), &name, size / 4)
+}
+
+pub(crate) fn generate_value_extractor(size_in_bits: u32, target: TargetLang) -> String {
+    if size_in_bits > 31 {
+        panic!("nonsensical to generate an extractor for a value with bit size greater than 31");
+    }
+    let mut extractor: String = String::new();
+
+    let mask_width: usize = 2_usize.pow(size_in_bits) - 1;
+
+    let uint = target.backend().uint_typename();
+    let package = target.backend().package_name();
+    let params = format!(
+        "{}, {}",
+        target.backend().param(uint, "bit_shift"),
+        target.backend().param(uint, package)
+    );
+    write!(
+        extractor,
+        "{}",
+        target.backend().fn_header(uint, &format!("extract_{}bit_value", size_in_bits), &params)
+    )
+        .unwrap();
+    write!(
+        extractor,
+        "{}",
+        target.backend().var_decl(uint, "mask", Some(&target.backend().uint_literal(mask_width)))
+    )
+        .unwrap();
+    write!(
+        extractor,
+        "{}",
+        target.backend().var_decl(
+            uint,
+            "result",
+            Some(&format!("({} >> bit_shift) & mask", package))
+        )
+    )
+        .unwrap();
+    write!(extractor, "\n    return result;\n}}\n\n").unwrap();
+
+    extractor
+}
+
+/// Generate an extractor for a signed value, which sign-extends the extracted bits.
+pub(crate) fn generate_signed_value_extractor(size_in_bits: u32, target: TargetLang) -> String {
+    if size_in_bits > 31 {
+        panic!("nonsensical to generate an extractor for a value with bit size greater than 31");
+    }
+    let mut extractor: String = String::new();
+
+    let uint = target.backend().uint_typename();
+    let int = GpuScalar::I32.typename(target);
+    let package = target.backend().package_name();
+    let params = format!(
+        "{}, {}",
+        target.backend().param(uint, "bit_shift"),
+        target.backend().param(uint, package)
+    );
+    write!(
+        extractor,
+        "{}",
+        target.backend().fn_header(int, &format!("extract_{}bit_signed_value", size_in_bits), &params)
+    )
+        .unwrap();
+    // Shift the value to the top of the word, then arithmetic shift it back down.
+    let spare_bits = target.backend().uint_literal(32 - size_in_bits as usize);
+    write!(
+        extractor,
+        "{}",
+        target.backend().var_decl(
+            uint,
+            "shifted",
+            Some(&format!("{} << ({} - bit_shift)", package, spare_bits))
+        )
+    )
+        .unwrap();
+    write!(
+        extractor,
+        "{}",
+        target.backend().var_decl(
+            int,
+            "result",
+            Some(&format!("{} >> {}", GpuScalar::I32.cvt("shifted", target), spare_bits))
+        )
+    )
+        .unwrap();
+    write!(extractor, "\n    return result;\n}}\n\n").unwrap();
+
+    extractor
+}
+
+/// Generate the inverse of `generate_value_extractor`, which replaces the bits of
+/// a value within a package.
+pub(crate) fn generate_value_inserter(size_in_bits: u32, target: TargetLang) -> String {
+    if size_in_bits > 31 {
+        panic!("nonsensical to generate an inserter for a value with bit size greater than 31");
+    }
+    let mut inserter: String = String::new();
+
+    let mask_width: usize = 2_usize.pow(size_in_bits) - 1;
+
+    let uint = target.backend().uint_typename();
+    let package = target.backend().package_name();
+    let params = format!(
+        "{}, {}, {}",
+        target.backend().param(uint, "bit_shift"),
+        target.backend().param(uint, package),
+        target.backend().param(uint, "value")
+    );
+    write!(
+        inserter,
+        "{}",
+        target.backend().fn_header(uint, &format!("insert_{}bit_value", size_in_bits), &params)
+    )
+        .unwrap();
+    write!(
+        inserter,
+        "{}",
+        target.backend().var_decl(uint, "mask", Some(&target.backend().uint_literal(mask_width)))
+    )
+        .unwrap();
+    write!(
+        inserter,
+        "{}",
+        target.backend().var_decl(
+            uint,
+            "result",
+            Some(&format!(
+                "({} & ~(mask << bit_shift)) | ((value & mask) << bit_shift)",
+                package
+            ))
+        )
+    )
+        .unwrap();
+    write!(inserter, "\n    return result;\n}}\n\n").unwrap();
+
+    inserter
+}
+
+/// The shader code of a definition, in parts that either use the module's
+/// buffers or not. Modules sharing a type share the parts that don't.
+#[derive(Default)]
+pub(crate) struct DefCode {
+    parts: Vec<(bool, String)>,
+}
+
+impl DefCode {
+    fn push(&mut self, uses_buffers: bool, code: String) {
+        self.parts.push((uses_buffers, code));
+    }
+
+    /// The parts that use the buffers, or those that don't, in order.
+    fn parts(&self, uses_buffers: bool) -> String {
+        self.parts
+            .iter()
+            .filter(|(uses, _)| *uses == uses_buffers)
+            .map(|(_, code)| code.as_str())
+            .collect()
+    }
+
+    fn all(&self) -> String {
+        self.parts.iter().map(|(_, code)| code.as_str()).collect()
+    }
+}
+
+/// A piece of a module's shader code. Pieces with the same key are the same
+/// in every module, so a kernel combining modules needs only one of them.
+pub(crate) struct Chunk {
+    /// The key, which is also the include guard of guarded pieces; pieces
+    /// without one are always included.
+    key: Option<String>,
+    guarded: bool,
+    code: String,
+}
+
+impl Chunk {
+    fn guarded(key: String, code: String) -> Chunk {
+        Chunk { key: Some(key), guarded: true, code }
+    }
+
+    fn keyed(key: String, code: String) -> Chunk {
+        Chunk { key: Some(key), guarded: false, code }
+    }
+}
+
+/// The shader code of the modules of a kernel, without repeating the pieces
+/// they share.
+pub(crate) fn combined_shader(modules: &[&GpuModule], target: TargetLang) -> String {
+    let mut r = String::new();
+    let mut seen = HashSet::new();
+    for module in modules {
+        for chunk in module.shader_chunks(target) {
+            if let Some(key) = &chunk.key {
+                if !seen.insert(key.clone()) {
+                    continue;
+                }
+            }
+            match (&chunk.key, chunk.guarded) {
+                (Some(key), true) => r.push_str(&target.backend().include_guard(key, &chunk.code)),
+                _ => r.push_str(&chunk.code),
+            }
+        }
+    }
+    r
+}
+
+pub(crate) struct SpecifiedStruct {
+    name: String,
+    fields: Vec<(String, GpuType)>,
+    packed_form: PackedStruct,
+}
+
+impl StoredField {
+    pub(crate) fn generate_unpacker(
+        &self,
+        packed_struct_name: &str,
+        packed_field_name: &str,
+        target: TargetLang,
+    ) -> String {
+        let mut unpacker = String::new();
+
+        // A hack to get the base struct name
+        let stripped_name = &packed_struct_name[0..packed_struct_name.len() - 6];
+        if self.ty.is_small() {
+            match self.ty {
+                GpuType::Scalar(scalar) => {
+                    let size_in_bits = scalar.bits();
+                    let unpacked_typename: String = match scalar {
+                        GpuScalar::F32 | GpuScalar::I32 | GpuScalar::U32 => {
+                            panic!("unexpected unpacking of 32 bit value!")
+                        }
+                        _ => String::from(scalar.unpacked_type(target).typename(target)),
+                    };
+
+                    writeln!(
+                        unpacker,
+                        "{}{}",
+                        target.backend().fn_header(
+                            &unpacked_typename,
+                            &format!("{}_unpack_{}", stripped_name, self.name),
+                            &target.backend().param(target.backend().uint_typename(), packed_field_name),
+                        ),
+                        target.backend().var_decl(&unpacked_typename, "result", None),
+                    )
+                        .unwrap();
+
+                    let extracted = scalar.cvt(
+                        &format!(
+                            "extract_{}bit_{}value({}, {})",
+                            size_in_bits,
+                            scalar.extractor_kind(),
+                            target.backend().uint_literal(self.offset),
+                            packed_field_name
+                        ),
+                        target,
+                    );
+                    writeln!(unpacker, "    result = {};", extracted).unwrap();
+                }
+                GpuType::Vector(scalar, unpacked_size) => {
+                    let scalar_size_in_bits = 8 * scalar.size();
+                    let unpacked_typename = self.ty.unpacked_typename(target);
+
+                    let size_in_uints = size_in_uints(scalar.size() * unpacked_size);
+                    writeln!(
+                        unpacker,
+                        "{}{}",
+                        target.backend().fn_header(
+                            &unpacked_typename,
+                            &format!("{}_unpack_{}", stripped_name, self.name),
+                            &target.backend().param(
+                                &target.backend().vector_typename(GpuScalar::U32, size_in_uints),
+                                packed_field_name
+                            ),
+                        ),
+                        target.backend().var_decl(&unpacked_typename, "result", None),
+                    )
+                        .unwrap();
+
+                    for i in 0..unpacked_size {
+                        let subscript = if size_in_uints == 1 {
+                            "".into()
+                        } else {
+                            format!("[{}]", (i * scalar_size_in_bits) / 32)
+                        };
+                        let extracted = scalar.cvt(
+                            &format!(
+                                "extract_{}bit_{}value({}, {}{})",
+                                scalar_size_in_bits,
+                                scalar.extractor_kind(),
+                                target.backend().uint_literal(self.offset + (i * scalar_size_in_bits) % 32),
+                                packed_field_name,
+                                subscript
+                            ),
+                            target,
+                        );
+                        writeln!(unpacker, "    result[{}] = {};", i, extracted).unwrap();
+                    }
+                }
+                _ => panic!(
+                    "only expected small types, got: {}",
+                    self.ty.unpacked_typename(target)
+                ),
+            }
+
+            writeln!(unpacker, "    return result;").unwrap();
+            write!(unpacker, "}}\n\n").unwrap();
+        }
+
+        unpacker
+    }
+
+    /// Generate statements inserting this field's value (a parameter with the
+    /// field's name) into the package `result`, the inverse of `generate_unpacker`.
+    pub(crate) fn generate_inserter(&self, package_size_in_uints: usize, target: TargetLang) -> String {
+        let mut inserter = String::new();
+        match self.ty {
+            GpuType::Scalar(scalar) => {
+                writeln!(
+                    inserter,
+                    "    result = insert_{}bit_value({}, result, {});",
+                    scalar.bits(),
+                    target.backend().uint_literal(self.offset),
+                    scalar.cvt_to_uint_vec(&self.name, 1, target),
+                )
+                    .unwrap();
+            }
+            GpuType::Vector(scalar, unpacked_size) => {
+                let scalar_size_in_bits = 8 * scalar.size();
+                for i in 0..unpacked_size {
+                    let package = if package_size_in_uints == 1 {
+                        "result".into()
+                    } else {
+                        format!("result[{}]", (i * scalar_size_in_bits) / 32)
+                    };
+                    writeln!(
+                        inserter,
+                        "    {} = insert_{}bit_value({}, {}, {});",
+                        package,
+                        scalar_size_in_bits,
+                        target.backend().uint_literal(self.offset + (i * scalar_size_in_bits) % 32),
+                        package,
+                        scalar.cvt_to_uint_vec(&format!("{}[{}]", self.name, i), 1, target),
+                    )
+                        .unwrap();
+                }
+            }
+            _ => panic!(
+                "only expected small types, got: {}",
+                self.ty.unpacked_typename(target)
+            ),
+        }
+        inserter
+    }
+}
+
+impl PackedField {
+    pub(crate) fn generate_reader(
+        &self,
+        module: &GpuModule,
+        current_offset: usize,
+        target: TargetLang,
+    ) -> Result<String, String> {
+        if let Some(ty) = &self.ty {
+            let type_name = ty.unpacked_typename(target);
+            let packed_field_name = &self.name;
+
+            match ty {
+                GpuType::Scalar(scalar) => {
+                    let load_expr = target.backend().load_expr(module, current_offset, 1);
+                    let cvt_exp = scalar.cvt(&load_expr, target);
+                    Ok(target.backend().var_decl(&type_name, packed_field_name, Some(&cvt_exp)))
+                }
+                GpuType::Vector(scalar, size) => {
+                    let size_in_uints = size_in_uints(scalar.size() * size);
+                    let load_expr = target.backend().load_expr(module, current_offset, size_in_uints);
+                    let cvt_exp = scalar.cvt_vec(&load_expr, *size, target);
+                    Ok(target.backend().var_decl(
+                        &target.backend().vector_typename(*scalar, *size),
+                        packed_field_name,
+                        Some(&cvt_exp),
+                    ))
+                }
+                GpuType::InlineStruct(isn) => Ok(target.backend().var_decl(
+                    &format!("{}Packed", isn),
+                    packed_field_name,
+                    Some(&format!(
+                        "{}_read({}{})",
+                        module.buf_fn_name(isn, target),
+                        target.backend().buf_call_arg(),
+                        target.backend().add_offset(target.backend().ref_name(), current_offset)
+                    )),
+                )),
+                GpuType::Ref(_) | GpuType::NullableRef(_) => Ok(target.backend().var_decl(
+                    &type_name,
+                    packed_field_name,
+                    Some(&target.backend().load_expr(module, current_offset, 1)),
+                )),
+                GpuType::Slice(_) => Ok(target.backend().var_decl(
+                    &type_name,
+                    packed_field_name,
+                    Some(&target.backend().load_expr(module, current_offset, 2)),
+                )),
+                GpuType::Affine2 => Ok(target.backend().var_decl(
+                    &type_name,
+                    packed_field_name,
+                    Some(&target.backend().affine_load_expr(module, current_offset)),
+                )),
+                // Arrays can't be assigned in every target, so read into the result directly.
+                GpuType::Array(elem, len) => {
+                    let mut reader = String::new();
+                    for i in 0..*len {
+                        writeln!(
+                            reader,
+                            "    result.{}[{}] = {};",
+                            packed_field_name,
+                            i,
+                            elem.elem_read_expr(
+                                module,
+                                target.backend().ref_name(),
+                                current_offset + i * elem.size(module),
+                                target
+                            )
+                        )
+                            .unwrap();
+                    }
+                    Ok(reader)
+                }
+            }
+        } else {
+            Err("cannot generate field reader from an open packed field".into())
+        }
+    }
+
+    pub(crate) fn generate_accessor(
+        &self,
+        packed_struct_name: &str,
+        ref_type: &str,
+        reader: &str,
+        target: TargetLang,
+    ) -> Result<String, String> {
+        if let Some(ty) = &self.ty {
+            let mut field_accessor = String::new();
+
+            let ret_type = match ty {
+                GpuType::InlineStruct(name) => format!("{}Packed", name),
+                _ => ty.unpacked_typename(target),
+            };
+            write!(
+                field_accessor,
+                "{}",
+                target.backend().fn_header(
+                    &ret_type,
+                    &format!("{}_{}", packed_struct_name, self.name),
+                    &target.backend().buf_and_ref_args(ref_type),
+                ),
+            )
+                .unwrap();
+            write!(field_accessor, "{}", reader).unwrap();
+            write!(field_accessor, "    return {};\n}}\n\n", self.name).unwrap();
+
+            Ok(field_accessor)
+        } else {
+            Err("cannot generate field accessor from open packed field".into())
+        }
+    }
+
+    /// Generate an indexed accessor for an array field.
+    pub(crate) fn generate_array_accessor(
+        &self,
+        module: &GpuModule,
+        packed_struct_name: &str,
+        ref_type: &str,
+        current_offset: usize,
+        target: TargetLang,
+    ) -> Result<String, String> {
+        if let Some(GpuType::Array(elem, _)) = &self.ty {
+            let mut accessor = String::new();
+            let ret_type = match elem.deref() {
+                GpuType::InlineStruct(name) => format!("{}Packed", name),
+                _ => elem.unpacked_typename(target),
+            };
+            let params = format!(
+                "{}, {}",
+                target.backend().buf_and_ref_args(ref_type),
+                target.backend().param(target.backend().uint_typename(), "ix")
+            );
+            write!(
+                accessor,
+                "{}{}",
+                target.backend().fn_header(
+                    &ret_type,
+                    &format!("{}_{}", packed_struct_name, self.name),
+                    &params
+                ),
+                target.backend().var_decl(
+                    target.backend().uint_typename(),
+                    "elem_ref",
+                    Some(&format!(
+                        "{} + ix * {}",
+                        target.backend().ref_name(),
+                        target.backend().uint_literal(elem.size(module))
+                    ))
+                ),
+            )
+                .unwrap();
+            write!(
+                accessor,
+                "    return {};\n}}\n\n",
+                elem.elem_read_expr(module, "elem_ref", current_offset, target)
+            )
+                .unwrap();
+            Ok(accessor)
+        } else {
+            Err("cannot generate array accessor for a non-array field".into())
+        }
+    }
+
+    /// Generate an indexed setter for an array field.
+    pub(crate) fn generate_array_setter(
+        &self,
+        module: &GpuModule,
+        packed_struct_name: &str,
+        ref_type: &str,
+        current_offset: usize,
+        target: TargetLang,
+    ) -> Result<String, String> {
+        if let Some(GpuType::Array(elem, _)) = &self.ty {
+            let mut setter = String::new();
+            let value_type = match elem.deref() {
+                GpuType::InlineStruct(name) => format!("{}Packed", name),
+                _ => elem.unpacked_typename(target),
+            };
+            let params = format!(
+                "{}, {}, {}",
+                target.backend().rw_buf_and_ref_args(ref_type),
+                target.backend().param(target.backend().uint_typename(), "ix"),
+                target.backend().param(&value_type, &self.name)
+            );
+            write!(
+                setter,
+                "{}{}",
+                target.backend().fn_header(
+                    "void",
+                    &format!("{}_set_{}", packed_struct_name, self.name),
+                    &params
+                ),
+                target.backend().var_decl(
+                    target.backend().uint_typename(),
+                    "elem_ref",
+                    Some(&format!(
+                        "{} + ix * {}",
+                        target.backend().ref_name(),
+                        target.backend().uint_literal(elem.size(module))
+                    ))
+                ),
+            )
+                .unwrap();
+            write!(
+                setter,
+                "{}}}\n\n",
+                elem.elem_store_stmt(module, "elem_ref", current_offset, &self.name, target)
+            )
+                .unwrap();
+            Ok(setter)
+        } else {
+            Err("cannot generate array setter for a non-array field".into())
+        }
+    }
+
+    /// Generate `_len` and `_index` accessors for a slice field. Struct elements
+    /// are returned as refs, other elements are loaded.
+    pub(crate) fn generate_slice_accessors(
+        &self,
+        module: &GpuModule,
+        packed_struct_name: &str,
+        ref_type: &str,
+        target: TargetLang,
+    ) -> Result<String, String> {
+        let elem = match &self.ty {
+            Some(GpuType::Slice(elem)) => elem.deref(),
+            Some(_) => return Ok(String::new()),
+            None => return Err("cannot generate slice accessors from open packed field".into()),
+        };
+        let mut accessors = String::new();
+        let uint = target.backend().uint_typename();
+        let header = format!(
+            "{}_{}({}{})",
+            packed_struct_name,
+            self.name,
+            target.backend().buf_call_arg(),
+            target.backend().ref_name()
+        );
+
+        write!(
+            accessors,
+            "{}    return {}[0];\n}}\n\n",
+            target.backend().fn_header(
+                uint,
+                &format!("{}_{}_len", packed_struct_name, self.name),
+                &target.backend().buf_and_ref_args(ref_type),
+            ),
+            header,
+        )
+            .unwrap();
+
+        let (ret_type, value) = match elem {
+            GpuType::InlineStruct(name) => (format!("{}Ref", name), "elem_ref".to_string()),
+            GpuType::Ref(_) | GpuType::NullableRef(_) => (
+                elem.unpacked_typename(target),
+                target.backend().load_expr_at(module, "elem_ref", 0, 1),
+            ),
+            GpuType::Scalar(scalar) if scalar.size() < 4 => {
+                // Small elements may share a word, so load the containing word.
+                let word_ref = format!("(elem_ref & ~{})", target.backend().uint_literal(3));
+                let extracted = format!(
+                    "extract_{}bit_{}value((elem_ref & {}) * {}, {})",
+                    8 * scalar.size(),
+                    scalar.extractor_kind(),
+                    target.backend().uint_literal(3),
+                    target.backend().uint_literal(8),
+                    target.backend().load_expr_at(module, &word_ref, 0, 1)
+                );
+                (elem.unpacked_typename(target), scalar.cvt(&extracted, target))
+            }
+            GpuType::Scalar(scalar) => (
+                elem.unpacked_typename(target),
+                scalar.cvt(&target.backend().load_expr_at(module, "elem_ref", 0, 1), target),
+            ),
+            GpuType::Vector(scalar, size) if scalar.size() == 4 => (
+                elem.unpacked_typename(target),
+                scalar.cvt_vec(&target.backend().load_expr_at(module, "elem_ref", 0, *size), *size, target),
+            ),
+            _ => return Err(format!("unsupported element type in slice {}", self.name)),
+        };
+        let params = format!(
+            "{}, {}",
+            target.backend().buf_and_ref_args(ref_type),
+            target.backend().param(uint, "ix")
+        );
+        write!(
+            accessors,
+            "{}",
+            target.backend().fn_header(
+                &ret_type,
+                &format!("{}_{}_index", packed_struct_name, self.name),
+                &params,
+            ),
+        )
+            .unwrap();
+        write!(
+            accessors,
+            "{}",
+            target.backend().var_decl(
+                &target.backend().vector_typename(GpuScalar::U32, 2),
+                "header",
+                Some(&header)
+            )
+        )
+            .unwrap();
+        write!(
+            accessors,
+            "{}",
+            target.backend().var_decl(
+                uint,
+                "elem_ref",
+                Some(&format!(
+                    "header[1] + ix * {}",
+                    target.backend().uint_literal(elem.size(module))
+                ))
+            )
+        )
+            .unwrap();
+        write!(accessors, "    return {};\n}}\n\n", value).unwrap();
+
+        Ok(accessors)
+    }
+
+    /// Generate `<Struct>_<field>_is_null` for an `Option<Ref<T>>` field.
+    pub(crate) fn generate_null_check(
+        &self,
+        packed_struct_name: &str,
+        ref_type: &str,
+        target: TargetLang,
+    ) -> Result<String, String> {
+        match &self.ty {
+            Some(GpuType::NullableRef(_)) => (),
+            Some(_) => return Ok(String::new()),
+            None => return Err("cannot generate null check from open packed field".into()),
+        }
+        let mut check = String::new();
+        write!(
+            check,
+            "{}    return {}_{}({}{}) == {};\n}}\n\n",
+            target.backend().fn_header(
+                GpuScalar::Bool.typename(target),
+                &format!("{}_{}_is_null", packed_struct_name, self.name),
+                &target.backend().buf_and_ref_args(ref_type),
+            ),
+            packed_struct_name,
+            self.name,
+            target.backend().buf_call_arg(),
+            target.backend().ref_name(),
+            target.backend().null_ref(),
+        )
+            .unwrap();
+        Ok(check)
+    }
+
+    pub(crate) fn generate_unpackers(&self, packed_struct_name: &str, target: TargetLang) -> String {
+        let mut unpackers = String::new();
+
+        for sf in &self.stored_fields {
+            write!(
+                unpackers,
+                "{}",
+                sf.generate_unpacker(packed_struct_name, &self.name, target)
+            )
+                .unwrap();
+        }
+
+        unpackers
+    }
+
+    /// Generate statements storing `value`, this field as held in the packed
+    /// struct, at the given offset.
+    pub(crate) fn generate_store(
+        &self,
+        module: &GpuModule,
+        current_offset: usize,
+        value: &str,
+        target: TargetLang,
+    ) -> Result<String, String> {
+        if let Some(ty) = &self.ty {
+            match ty {
+                GpuType::Scalar(scalar) => Ok(target.backend().store_stmt(
+                    module,
+                    current_offset,
+                    1,
+                    &scalar.cvt_to_uint_vec(value, 1, target),
+                )),
+                GpuType::Vector(scalar, size) => Ok(target.backend().store_stmt(
+                    module,
+                    current_offset,
+                    size_in_uints(scalar.size() * size),
+                    &scalar.cvt_to_uint_vec(value, *size, target),
+                )),
+                GpuType::InlineStruct(isn) => Ok(format!(
+                    "    {}_write({}{}, {});\n",
+                    module.buf_fn_name(isn, target),
+                    target.backend().buf_call_arg(),
+                    target.backend().add_offset(target.backend().ref_name(), current_offset),
+                    value,
+                )),
+                GpuType::Ref(_) | GpuType::NullableRef(_) => {
+                    Ok(target.backend().store_stmt(module, current_offset, 1, value))
+                }
+                GpuType::Slice(_) => Ok(target.backend().store_stmt(module, current_offset, 2, value)),
+                GpuType::Affine2 => Ok(target.backend().affine_store_stmts(module, current_offset, value)),
+                GpuType::Array(elem, len) => Ok((0..*len)
+                    .map(|i| {
+                        elem.elem_store_stmt(
+                            module,
+                            target.backend().ref_name(),
+                            current_offset + i * elem.size(module),
+                            &format!("{}[{}]", value, i),
+                            target,
+                        )
+                    })
+                    .collect()),
+            }
+        } else {
+            Err("cannot generate field store from an open packed field".into())
+        }
+    }
+
+    pub(crate) fn generate_setter(
+        &self,
+        packed_struct_name: &str,
+        ref_type: &str,
+        store: &str,
+        target: TargetLang,
+    ) -> Result<String, String> {
+        if let Some(ty) = &self.ty {
+            let mut field_setter = String::new();
+
+            let value_type = match ty {
+                GpuType::InlineStruct(name) => format!("{}Packed", name),
+                _ => ty.unpacked_typename(target),
+            };
+            let params = format!(
+                "{}, {}",
+                target.backend().rw_buf_and_ref_args(ref_type),
+                target.backend().param(&value_type, &self.name)
+            );
+            write!(
+                field_setter,
+                "{}",
+                target.backend().fn_header(
+                    "void",
+                    &format!("{}_set_{}", packed_struct_name, self.name),
+                    &params
+                ),
+            )
+                .unwrap();
+            write!(field_setter, "{}}}\n\n", store).unwrap();
+
+            Ok(field_setter)
+        } else {
+            Err("cannot generate field setter from open packed field".into())
+        }
+    }
+
+    /// Generate a function packing the stored fields into this field, the
+    /// inverse of the unpackers. A word shared with a narrow tag gets the tag.
+    pub(crate) fn generate_packer(
+        &self,
+        packed_struct: &PackedStruct,
+        target: TargetLang,
+    ) -> String {
+        let mut packer = String::new();
+
+        // A hack to get the base struct name
+        let packed_struct_name = &packed_struct.name;
+        let stripped_name = &packed_struct_name[0..packed_struct_name.len() - 6];
+        let size_in_uints = match self.ty {
+            Some(GpuType::Vector(_, size)) => size,
+            _ => 1,
+        };
+        let packed_typename = target.backend().vector_typename(GpuScalar::U32, size_in_uints);
+        let mut params = self
+            .stored_fields
+            .iter()
+            .map(|sf| target.backend().param(&sf.ty.unpacked_typename(target), &sf.name))
+            .collect::<Vec<String>>();
+        let init = if self.tag_bits > 0 {
+            if packed_struct.takes_tag() {
+                params.push(target.backend().param(target.backend().uint_typename(), "tag"));
+            }
+            packed_struct.tag_expr(target)
+        } else {
+            target.backend().zero_uint(size_in_uints)
+        };
+        writeln!(
+            packer,
+            "{}{}",
+            target.backend().fn_header(
+                &packed_typename,
+                &format!("{}_pack_{}", stripped_name, self.name),
+                &params.join(", "),
+            ),
+            target.backend().var_decl(&packed_typename, "result", Some(&init)),
+        )
+            .unwrap();
+        for sf in &self.stored_fields {
+            write!(packer, "{}", sf.generate_inserter(size_in_uints, target)).unwrap();
+        }
+        write!(packer, "\n    return result;\n}}\n\n").unwrap();
+
+        packer
+    }
+}
+
+impl PackedStruct {
+    /// Whether the packers of the struct take the tag as a parameter, as the
+    /// struct is held by more than one variant.
+    pub(crate) fn takes_tag(&self) -> bool {
+        self.is_enum_variant && self.tag.is_none()
+    }
+
+    /// The tag the packers store, as a shader expression.
+    pub(crate) fn tag_expr(&self, target: TargetLang) -> String {
+        match self.tag {
+            Some(tag) => target.backend().uint_literal(tag as usize),
+            None => "tag".into(),
+        }
+    }
+
+    pub(crate) fn generate_functions(&self, module: &GpuModule, code: &mut DefCode, target: TargetLang) {
+        let mut r = String::new();
+        let mut field_accessors: Vec<String> = Vec::new();
+        let mut unpackers: Vec<String> = Vec::new();
+        let mut field_setters: Vec<String> = Vec::new();
+        let mut packers: Vec<String> = Vec::new();
+
+        // This is something of a hack to strip the "Packed" off the struct name
+        let stripped_name = &self.name[0..self.name.len() - 6];
+        let ref_type = format!("{}Ref", stripped_name);
+        let fn_name = &module.buf_fn_name(stripped_name, target);
+
+        // The tag of enum variants is part of the packed struct, so the writer
+        // stores it along with the fields.
+        let mut writer = String::new();
+        write!(
+            writer,
+            "{}",
+            target.backend().fn_header(
+                "void",
+                &format!("{}_write", fn_name),
+                &format!(
+                    "{}, {}",
+                    target.backend().rw_buf_and_ref_args(&ref_type),
+                    target.backend().param(&self.name, "s")
+                ),
+            ),
+        )
+            .unwrap();
+
+        writeln!(
+            r,
+            "{}{}",
+            target.backend().fn_header(
+                &self.name,
+                &format!("{}_read", fn_name),
+                &target.backend().buf_and_ref_args(&ref_type),
+            ),
+            target.backend().var_decl(&self.name, "result", None),
+        )
+            .unwrap();
+        if self.has_tag_word() {
+            let tag = target.backend().load_expr(module, 0, 1);
+            write!(r, "    result.tag = {};\n\n", tag).unwrap();
+            write!(writer, "{}", target.backend().store_stmt(module, 0, 1, "s.tag")).unwrap();
+        }
+
+        let packed_field_offsets = self.packed_field_offsets(module);
+        for (packed_field, &current_offset) in self.packed_fields.iter().zip(&packed_field_offsets) {
+            let reader: String = packed_field
+                .generate_reader(module, current_offset, target)
+                .unwrap();
+            let is_array = matches!(packed_field.ty, Some(GpuType::Array(..)));
+            if is_array {
+                field_accessors.push(
+                    packed_field
+                        .generate_array_accessor(
+                            module,
+                            fn_name,
+                            &ref_type,
+                            current_offset,
+                            target,
+                        )
+                        .unwrap(),
+                );
+                field_setters.push(
+                    packed_field
+                        .generate_array_setter(
+                            module,
+                            fn_name,
+                            &ref_type,
+                            current_offset,
+                            target,
+                        )
+                        .unwrap(),
+                );
+            } else {
+                let field_accessor: String = packed_field
+                    .generate_accessor(fn_name, &ref_type, &reader, target)
+                    .unwrap();
+                field_accessors.push(field_accessor);
+
+                let store = packed_field
+                    .generate_store(module, current_offset, &packed_field.name, target)
+                    .unwrap();
+                field_setters.push(
+                    packed_field
+                        .generate_setter(fn_name, &ref_type, &store, target)
+                        .unwrap(),
+                );
+            }
+            field_accessors.push(
+                packed_field
+                    .generate_slice_accessors(module, fn_name, &ref_type, target)
+                    .unwrap(),
+            );
+            field_accessors.push(
+                packed_field
+                    .generate_null_check(fn_name, &ref_type, target)
+                    .unwrap(),
+            );
+            if packed_field.is_packed(false) {
+                unpackers.push(packed_field.generate_unpackers(&self.name, target));
+                packers.push(packed_field.generate_packer(self, target));
+            }
+
+            let write_field = packed_field
+                .generate_store(
+                    module,
+                    current_offset,
+                    &format!("s.{}", packed_field.name),
+                    target,
+                )
+                .unwrap();
+            write!(writer, "{}", write_field).unwrap();
+
+            write!(r, "{}", reader).unwrap();
+            if is_array {
+                writeln!(r).unwrap();
+            } else {
+                write!(
+                    r,
+                    "    result.{} = {};\n\n",
+                    packed_field.name, packed_field.name
+                )
+                    .unwrap();
+            }
+        }
+
+        write!(r, "    return result;\n}}\n\n",).unwrap();
+
+        for field_accessor in field_accessors {
+            write!(r, "{}", field_accessor).unwrap();
+        }
+        code.push(true, r);
+        code.push(false, unpackers.concat());
+
+        write!(writer, "}}\n\n").unwrap();
+        for field_setter in field_setters {
+            write!(writer, "{}", field_setter).unwrap();
+        }
+        code.push(true, target.backend().writers(module, &writer));
+        code.push(false, packers.concat());
+    }
+
+    pub(crate) fn generate_structure_def(&self, module: &GpuModule, target: TargetLang) -> String {
+        let mut r = String::new();
+
+        // The packed struct definition (is missing variable sized arrays)
+        writeln!(r, "struct {} {{", self.name).unwrap();
+        let mut end = 0;
+        if self.has_tag_word() {
+            write!(r, "{}", target.backend().struct_field(target.backend().uint_typename(), "tag")).unwrap();
+            end = 4;
+        }
+
+        // Padding is explicit, so that the struct matches the encoded layout.
+        let offsets = self.packed_field_offsets(module);
+        let mut pad_ix = 0;
+        for (packed_field, &offset) in self.packed_fields.iter().zip(&offsets) {
+            write!(r, "{}", padding_field(offset - end, &mut pad_ix, target)).unwrap();
+            end = offset + packed_field.size(module).unwrap();
+            let ty = packed_field
+                .ty
+                .as_ref()
+                .unwrap_or_else(|| panic!("packed field {} has no type", packed_field.name));
+            if let GpuType::Array(elem, len) = ty {
+                let typename = elem.packed_typename(target);
+                write!(r, "{}", target.backend().struct_array_field(&typename, &packed_field.name, *len))
+                    .unwrap();
+                continue;
+            }
+            let typename = ty.packed_typename(target);
+            write!(r, "{}", target.backend().struct_field(&typename, &packed_field.name)).unwrap()
+        }
+        write!(r, "{}", padding_field(self.size(module) - end, &mut pad_ix, target)).unwrap();
+        write!(r, "}};\n\n").unwrap();
+
+        r
+    }
+
+    pub(crate) fn to_shader(&self, module: &GpuModule, code: &mut DefCode, target: TargetLang) {
+        code.push(false, self.generate_structure_def(module, target));
+        self.generate_functions(module, code, target);
+    }
+}
+
+impl SpecifiedStruct {
+    pub(crate) fn new(module: &GpuModule, name: &str, fields: Vec<(String, GpuType)>) -> SpecifiedStruct {
+        let packed_form = PackedStruct::new(module, name, &fields);
+
+        SpecifiedStruct {
+            name: name.to_string(),
+            fields,
+            packed_form,
+        }
+    }
+
+    pub(crate) fn generate_structure_def(&self, target: TargetLang) -> String {
+        let mut r = String::new();
+
+        // The unpacked struct definition (is missing variable sized arrays)
+        writeln!(r, "struct {} {{", self.name).unwrap();
+
+        for (field_name, field_type) in self.fields.iter() {
+            let field = match field_type {
+                GpuType::Array(elem, len) => {
+                    target.backend().struct_array_field(&elem.unpacked_typename(target), field_name, *len)
+                }
+                _ => target.backend().struct_field(&field_type.unpacked_typename(target), field_name),
+            };
+            write!(r, "{}", field).unwrap()
+        }
+        write!(r, "}};\n\n").unwrap();
+
+        r
+    }
+
+    pub(crate) fn generate_unpacker(&self, target: TargetLang) -> String {
+        let mut r = String::new();
+
+        writeln!(
+            r,
+            "{}{}",
+            target.backend().fn_header(
+                &self.name,
+                &format!("{}_unpack", self.name),
+                &target.backend().param(&self.packed_form.name, "packed_form"),
+            ),
+            target.backend().var_decl(&self.name, "result", None),
+        )
+            .unwrap();
+        for (field_name, field_type) in self.fields.iter() {
+            let packed_field = self
+                .packed_form
+                .packed_fields
+                .iter()
+                .find(|&pf| {
+                    pf.stored_fields
+                        .iter()
+                        .find(|&sf| sf.name == field_name.as_str())
+                        .is_some()
+                })
+                .unwrap_or_else(|| {
+                    panic!("no packed field stores {} in {}Packed", field_name, self.name)
+                });
+            if let GpuType::Array(elem, len) = field_type {
+                for i in 0..*len {
+                    let value = match elem.deref() {
+                        GpuType::InlineStruct(name) => {
+                            format!("{}_unpack(packed_form.{}[{}])", name, packed_field.name, i)
+                        }
+                        _ => format!("packed_form.{}[{}]", packed_field.name, i),
+                    };
+                    writeln!(r, "    result.{}[{}] = {};", field_name, i, value).unwrap();
+                }
+            } else if packed_field.is_packed(true) {
+                match field_type {
+                    GpuType::InlineStruct(name) => {
+                        writeln!(
+                            r,
+                            "    result.{} = {}_unpack(packed_form.{});",
+                            field_name, name, packed_field.name
+                        )
+                            .unwrap();
+                    }
+                    _ => {
+                        writeln!(
+                            r,
+                            "    result.{} = {}_unpack_{}(packed_form.{});",
+                            field_name, self.name, field_name, packed_field.name
+                        )
+                            .unwrap();
+                    }
+                }
+            } else {
+                writeln!(
+                    r,
+                    "    result.{} = packed_form.{};",
+                    field_name, packed_field.name
+                )
+                    .unwrap();
+            }
+        }
+        write!(r, "\n    return result;\n}}\n\n").unwrap();
+        r
+    }
+
+    pub(crate) fn generate_packer(&self, target: TargetLang) -> String {
+        let mut r = String::new();
+
+        // Structs held by more than one variant are packed with the tag given.
+        let takes_tag = self.packed_form.takes_tag();
+        let mut params = target.backend().param(&self.name, "unpacked");
+        if takes_tag {
+            let uint = target.backend().uint_typename();
+            write!(params, ", {}", target.backend().param(uint, "tag")).unwrap();
+        }
+        writeln!(
+            r,
+            "{}{}",
+            target.backend().fn_header(
+                &self.packed_form.name,
+                &format!("{}_pack", self.name),
+                &params,
+            ),
+            target.backend().var_decl(&self.packed_form.name, "result", None),
+        )
+            .unwrap();
+
+        if self.packed_form.has_tag_word() {
+            writeln!(r, "    result.tag = {};", self.packed_form.tag_expr(target)).unwrap();
+        }
+        for packed_field in &self.packed_form.packed_fields {
+            if packed_field.is_packed(false) {
+                let mut args = packed_field
+                    .stored_fields
+                    .iter()
+                    .map(|sf| format!("unpacked.{}", sf.name))
+                    .collect::<Vec<String>>();
+                if packed_field.tag_bits > 0 && takes_tag {
+                    args.push("tag".into());
+                }
+                let args = args.join(", ");
+                writeln!(
+                    r,
+                    "    result.{} = {}_pack_{}({});",
+                    packed_field.name, self.name, packed_field.name, args
+                )
+                    .unwrap();
+            } else {
+                let stored_field = &packed_field.stored_fields[0];
+                match &stored_field.ty {
+                    GpuType::Array(elem, len) => {
+                        for i in 0..*len {
+                            let value = match elem.deref() {
+                                GpuType::InlineStruct(name) => {
+                                    format!("{}_pack(unpacked.{}[{}])", name, stored_field.name, i)
+                                }
+                                _ => format!("unpacked.{}[{}]", stored_field.name, i),
+                            };
+                            writeln!(r, "    result.{}[{}] = {};", packed_field.name, i, value)
+                                .unwrap();
+                        }
+                    }
+                    GpuType::InlineStruct(name) => {
+                        writeln!(
+                            r,
+                            "    result.{} = {}_pack(unpacked.{});",
+                            packed_field.name, name, stored_field.name
+                        )
+                            .unwrap();
+                    }
+                    _ => {
+                        writeln!(
+                            r,
+                            "    result.{} = unpacked.{};",
+                            packed_field.name, stored_field.name
+                        )
+                            .unwrap();
+                    }
+                }
+            }
+        }
+        write!(r, "\n    return result;\n}}\n\n").unwrap();
+        r
+    }
+
+    pub(crate) fn to_shader(&self, target: TargetLang) -> String {
+        let mut r = String::new();
+
+        write!(r, "{}", self.generate_structure_def(target)).unwrap();
+        write!(r, "{}", self.generate_unpacker(target)).unwrap();
+        write!(r, "{}", self.generate_packer(target)).unwrap();
+
+        r
+    }
+}
+
+impl GpuType {
+    // The type name for the *unpacked* version of the type.
+    pub(crate) fn unpacked_typename(&self, target: TargetLang) -> String {
+        match self {
+            GpuType::Scalar(scalar) => scalar.unpacked_type(target).typename(target).into(),
+            GpuType::Vector(scalar, size) => {
+                target.backend().vector_typename(scalar.unpacked_type(target), *size)
+            }
+            GpuType::InlineStruct(name) => name.to_string(),
+            // TODO: probably want to have more friendly names for simple struct refs.
+            GpuType::Ref(inner) | GpuType::NullableRef(inner) => {
+                if let GpuType::InlineStruct(name) = inner.deref() {
+                    format!("{}Ref", name)
+                } else {
+                    target.backend().uint_typename().into()
+                }
+            }
+            // The length and offset of the elements.
+            GpuType::Slice(_) => target.backend().vector_typename(GpuScalar::U32, 2),
+            // Arrays are declared with `struct_array_field`; this is the element.
+            GpuType::Array(elem, _) => elem.unpacked_typename(target),
+            GpuType::Affine2 => target.backend().affine_typename().into(),
+        }
+    }
+
+    /// An expression reading an array element at the given offset from the
+    /// byte offset in `ref_name`.
+    pub(crate) fn elem_read_expr(
+        &self,
+        module: &GpuModule,
+        ref_name: &str,
+        offset: usize,
+        target: TargetLang,
+    ) -> String {
+        match self {
+            GpuType::InlineStruct(name) => format!(
+                "{}_read({}{})",
+                module.buf_fn_name(name, target),
+                target.backend().buf_call_arg(),
+                target.backend().add_offset(ref_name, offset)
+            ),
+            GpuType::Vector(scalar, size) => {
+                scalar.cvt_vec(&target.backend().load_expr_at(module, ref_name, offset, *size), *size, target)
+            }
+            _ => target.backend().load_expr_at(module, ref_name, offset, 1),
+        }
+    }
+
+    /// A statement writing an array element, the inverse of `elem_read_expr`.
+    pub(crate) fn elem_store_stmt(
+        &self,
+        module: &GpuModule,
+        ref_name: &str,
+        offset: usize,
+        value: &str,
+        target: TargetLang,
+    ) -> String {
+        match self {
+            GpuType::InlineStruct(name) => format!(
+                "    {}_write({}{}, {});\n",
+                module.buf_fn_name(name, target),
+                target.backend().buf_call_arg(),
+                target.backend().add_offset(ref_name, offset),
+                value
+            ),
+            GpuType::Vector(scalar, size) => target.backend().store_stmt_at(
+                module,
+                ref_name,
+                offset,
+                *size,
+                &scalar.cvt_to_uint_vec(value, *size, target),
+            ),
+            _ => target.backend().store_stmt_at(module, ref_name, offset, 1, value),
+        }
+    }
+
+    /// The type name as stored in a packed struct.
+    pub(crate) fn packed_typename(&self, target: TargetLang) -> String {
+        match self {
+            // a packed struct will only store the packed version of any structs
+            GpuType::InlineStruct(name) => format!("{}Packed", name),
+            GpuType::Vector(_, size) if *size > 1 => {
+                target.backend().packed_vector_typename(self.unpacked_typename(target))
+            }
+            // The header is two words, which may be only 4 aligned.
+            GpuType::Slice(_) => target.backend().packed_vector_typename(self.unpacked_typename(target)),
+            _ => self.unpacked_typename(target),
+        }
+    }
+}
+
+impl GpuTypeDef {
+    /// Generate `_copy` functions moving the encoded words of the type to the
+    /// writable buffer, from the read-only buffer or, with `_rw`, from the
+    /// writable one; and `_copy_range` variants moving `n` consecutive elements.
+    pub(crate) fn generate_copy_functions(&self, module: &GpuModule, target: TargetLang) -> String {
+        let name = module.buf_fn_name(self.name(), target);
+        let size = self.size(module);
+        let uint = target.backend().uint_typename();
+        let mut r = String::new();
+        let mut ranges = String::new();
+        for &rw in &[false, true] {
+            let suffix = if rw { "_rw" } else { "" };
+            let (buf_params, buf_args) = target.backend().copy_params(rw);
+            write!(
+                r,
+                "{}",
+                target.backend().fn_header("void", &format!("{}_copy{}", name, suffix), &buf_params)
+            )
+                .unwrap();
+            r.push_str(&target.backend().copy_body(module, size, rw));
+            write!(r, "}}\n\n").unwrap();
+
+            // Elements of an array are `size` bytes apart, as the size
+            // includes any padding for alignment.
+            let range_params = format!("{}, {}", buf_params, target.backend().param(uint, "n"));
+            let stride = target.backend().uint_literal(size);
+            let args = buf_args
+                .replacen("{}", &format!("src_ref + i * {}", stride), 1)
+                .replacen("{}", &format!("dst_ref + i * {}", stride), 1);
+            let header = target.backend().for_range_header();
+            write!(
+                ranges,
+                "{}    {} {{\n        {}_copy{}({});\n    }}\n}}\n\n",
+                target.backend().fn_header("void", &format!("{}_copy_range{}", name, suffix), &range_params),
+                header,
+                name,
+                suffix,
+                args
+            )
+                .unwrap();
+        }
+        r.push_str(&ranges);
+        target.backend().writers(module, &r)
+    }
+
+    pub(crate) fn to_shader(&self, module: &GpuModule, target: TargetLang) -> DefCode {
+        let mut code = DefCode::default();
+        match self {
+            GpuTypeDef::Struct(name, fields) => {
+                let structure = SpecifiedStruct::new(module, name, fields.clone());
+                structure.packed_form.to_shader(module, &mut code, target);
+                code.push(false, structure.to_shader(target));
+            }
+            GpuTypeDef::Enum(en) => {
+                let rn = format!("{}Ref", en.name);
+                let fn_name = module.buf_fn_name(&en.name, target);
+
+                let uint = target.backend().uint_typename();
+                let mut r = String::new();
+                writeln!(r, "struct {} {{", en.name).unwrap();
+                write!(r, "{}", target.backend().struct_field(uint, "tag")).unwrap();
+
+                let size = self.size(module);
+                let body_size = ((size + 3) >> 2) - 1;
+
+                // Zero-sized arrays aren't allowed, and enums of unit variants or
+                // narrow tags may have no body.
+                if body_size > 0 {
+                    write!(r, "{}", target.backend().struct_array_field(uint, "body", body_size)).unwrap();
+                }
+                writeln!(r, "}};").unwrap();
+                code.push(false, r);
+
+                let mut r = String::new();
+                write!(
+                    r,
+                    "{}",
+                    target.backend().fn_header(
+                        uint,
+                        &format!("{}_tag", fn_name),
+                        &target.backend().buf_and_ref_args(&rn),
+                    ),
+                )
+                    .unwrap();
+                // A narrow tag shares its word with the fields of the variant.
+                let zero = target.backend().uint_literal(0);
+                let (tag, stored_tag) = if en.tag_size < 4 {
+                    let bits = 8 * en.tag_size;
+                    (
+                        format!(
+                            "extract_{}bit_value({}, {})",
+                            bits,
+                            zero,
+                            target.backend().load_expr(module, 0, 1)
+                        ),
+                        format!(
+                            "insert_{}bit_value({}, {}, tag)",
+                            bits,
+                            zero,
+                            target.backend().rw_load_expr(module, 0)
+                        ),
+                    )
+                } else {
+                    (target.backend().load_expr(module, 0, 1), "tag".to_string())
+                };
+                writeln!(
+                    r,
+                    "{}    return result;",
+                    target.backend().var_decl(uint, "result", Some(&tag))
+                )
+                    .unwrap();
+                write!(r, "}}\n\n").unwrap();
+
+                // We don't write individual enum structs, we only write their variants.
+                let write_tag = format!(
+                    "{}{}}}\n\n",
+                    target.backend().fn_header(
+                        "void",
+                        &format!("{}_write_tag", fn_name),
+                        &format!(
+                            "{}, {}",
+                            target.backend().rw_buf_and_ref_args(&rn),
+                            target.backend().param(uint, "tag")
+                        ),
+                    ),
+                    target.backend().store_stmt(module, 0, 1, &stored_tag),
+                );
+                r.push_str(&target.backend().writers(module, &write_tag));
+                code.push(true, r);
+            }
+        }
+        code.push(true, self.generate_copy_functions(module, target));
+        code.push(true, target.backend().def_extras(self));
+        code
+    }
+}
+
+impl GpuModule {
+    /// Name of the GLSL/WGSL buffer array the module's readers load from.
+    pub(crate) fn buf_name(&self) -> String {
+        format!("{}_buf", self.name)
+    }
+
+    /// Name of the GLSL/WGSL buffer array the module's copy helpers store to.
+    pub(crate) fn dst_buf_name(&self) -> String {
+        format!("{}_dst_buf", self.name)
+    }
+
+    /// Whether the definition `name` comes from another module or a derive.
+    pub(crate) fn is_imported(&self, name: &str) -> bool {
+        self.def_modules[name] != self.name
+    }
+
+    /// The start of the names of the functions of the definition `name` that
+    /// read or write the buffers, as in `<name>_read`. In GLSL and WGSL, those
+    /// of imported types get the module's name in front, as they use the
+    /// buffers of this module rather than those of the module defining them.
+    pub(crate) fn buf_fn_name(&self, name: &str, target: TargetLang) -> String {
+        if self.is_imported(name) && !target.backend().passes_buffers() {
+            format!("{}_{}", self.name, name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// The widths of the bit fields in the module, other than those the
+    /// extractors are always generated for, and whether any are signed.
+    pub(crate) fn bitfield_widths(&self) -> BTreeMap<u32, bool> {
+        let mut widths = BTreeMap::new();
+        for def in &self.defs {
+            if let GpuTypeDef::Struct(_, fields) = def {
+                for (_, ty) in fields {
+                    if let GpuType::Scalar(scalar) = ty {
+                        let bits = scalar.bits() as u32;
+                        if scalar.is_bitfield() && bits != 8 && bits != 16 {
+                            let signed = widths.entry(bits).or_insert(false);
+                            *signed |= scalar.extractor_kind() == "signed_";
+                        }
+                    }
+                }
+            }
+        }
+        widths
+    }
+
+    pub(crate) fn to_shader(&self, target: TargetLang) -> String {
+        combined_shader(&[self], target)
+    }
+
+    /// The pieces of the module's shader code, in order.
+    pub(crate) fn shader_chunks(&self, target: TargetLang) -> Vec<Chunk> {
+        let mut chunks = Vec::new();
+
+        let mut helpers = Vec::new();
+        for &bits in &[8, 16] {
+            helpers.push((format!("extract_{}bit_value", bits), generate_value_extractor(bits, target)));
+        }
+        for &bits in &[8, 16] {
+            helpers.push((
+                format!("extract_{}bit_signed_value", bits),
+                generate_signed_value_extractor(bits, target),
+            ));
+        }
+        for &bits in &[8, 16] {
+            helpers.push((format!("insert_{}bit_value", bits), generate_value_inserter(bits, target)));
+        }
+        for (bits, signed) in self.bitfield_widths() {
+            helpers.push((format!("extract_{}bit_value", bits), generate_value_extractor(bits, target)));
+            if signed {
+                helpers.push((
+                    format!("extract_{}bit_signed_value", bits),
+                    generate_signed_value_extractor(bits, target),
+                ));
+            }
+            helpers.push((format!("insert_{}bit_value", bits), generate_value_inserter(bits, target)));
+        }
+        // The helpers and definitions are guarded, so that modules sharing
+        // them can be included together.
+        for (name, helper) in helpers {
+            chunks.push(Chunk::guarded(format!("PIET_GPU_{}", name.to_uppercase()), helper));
+        }
+
+        let module_key = format!("PIET_GPU_{}", self.name.to_uppercase());
+        chunks.push(Chunk::keyed(format!("{}_BUFFERS", module_key), target.backend().buffer_decls(self)));
+
+        for def in &self.defs {
+            chunks.push(Chunk::keyed(
+                format!("{}_REF", self.def_key(def.name())),
+                target.backend().ref_alias(def.name()),
+            ));
+        }
+
+        chunks.push(Chunk { key: None, guarded: false, code: "\n".into() });
+        for def in &self.defs {
+            let key = self.def_key(def.name());
+            let code = def.to_shader(self, target);
+            if self.buf_fn_name(def.name(), target) == def.name() {
+                chunks.push(Chunk::guarded(key, code.all()));
+            } else {
+                // The functions using the buffers are this module's own.
+                chunks.push(Chunk::guarded(key.clone(), code.parts(false)));
+                chunks.push(Chunk::guarded(
+                    format!("{}_IN_{}", key, self.name.to_uppercase()),
+                    code.parts(true),
+                ));
+            }
+        }
+
+        for def in &self.defs {
+            let name = def.name();
+            let mut r = String::new();
+            if !(self.enum_variants.contains_key(name)) {
+                write!(
+                    r,
+                    "{}",
+                    target.backend().define(
+                        &format!("{}_SIZE", to_snake_case(name).to_uppercase()),
+                        def.size(self)
+                    )
+                )
+                    .unwrap();
+            }
+            if let GpuTypeDef::Enum(en) = def {
+                for variant in &en.variants {
+                    write!(
+                        r,
+                        "{}",
+                        target.backend().define(&format!("{}_{}", en.name, variant.name), variant.tag as usize)
+                    )
+                        .unwrap();
+                }
+            }
+            chunks.push(Chunk::keyed(format!("{}_DEFINES", self.def_key(name)), r));
+        }
+        // Shaders only have 32 bit integers, so the hash is split in two.
+        let hash_name = format!("{}_SCHEMA_HASH", self.name.to_uppercase());
+        let hash = self.schema_hash();
+        let mut r = target.backend().define_hex(&format!("{}_LO", hash_name), hash as u32);
+        r.push_str(&target.backend().define_hex(&format!("{}_HI", hash_name), (hash >> 32) as u32));
+        chunks.push(Chunk::keyed(format!("{}_SCHEMA_HASH", module_key), r));
+        chunks
+    }
+
+    /// The include guard of a definition, which is the same in every module
+    /// using it.
+    fn def_key(&self, name: &str) -> String {
+        format!("PIET_GPU_{}_{}", self.def_modules[name], to_snake_case(name)).to_uppercase()
+    }
+}
@@ -0,0 +1,129 @@
+//! WGSL, reading from storage buffers declared by the module.
+
+use crate::backend::Backend;
+use crate::{GpuModule, GpuScalar};
+
+pub(crate) struct Wgsl;
+
+impl Backend for Wgsl {
+    /// `ref` is a reserved word in WGSL.
+    fn ref_name(&self) -> &'static str {
+        "ref_"
+    }
+
+    /// `package` is a reserved word in WGSL.
+    fn package_name(&self) -> &'static str {
+        "packed"
+    }
+
+    fn param(&self, ty: &str, name: &str) -> String {
+        format!("{}: {}", name, ty)
+    }
+
+    fn fn_header(&self, ret_type: &str, name: &str, params: &str) -> String {
+        if ret_type == "void" {
+            format!("fn {}({}) {{\n", name, params)
+        } else {
+            format!("fn {}({}) -> {} {{\n", name, params, ret_type)
+        }
+    }
+
+    fn for_range_header(&self) -> &'static str {
+        "for (var i = 0u; i < n; i = i + 1u)"
+    }
+
+    fn var_decl(&self, ty: &str, name: &str, init: Option<&str>) -> String {
+        match init {
+            Some(init) => format!("    var {}: {} = {};\n", name, ty, init),
+            None => format!("    var {}: {};\n", name, ty),
+        }
+    }
+
+    fn struct_field(&self, ty: &str, name: &str) -> String {
+        format!("    {}: {},\n", name, ty)
+    }
+
+    fn struct_array_field(&self, ty: &str, name: &str, len: usize) -> String {
+        format!("    {}: array<{}, {}>,\n", name, ty, len)
+    }
+
+    fn define(&self, name: &str, value: usize) -> String {
+        format!("const {}: u32 = {}u;\n", name, value)
+    }
+
+    fn define_hex(&self, name: &str, value: u32) -> String {
+        format!("const {}: u32 = {:#010x}u;\n", name, value)
+    }
+
+    /// WGSL has no preprocessor, so its code is unguarded.
+    fn include_guard(&self, _guard: &str, code: &str) -> String {
+        code.into()
+    }
+
+    /// Storage buffer declarations for the module, bound as in its
+    /// `#[bindings(..)]`.
+    fn buffer_decls(&self, module: &GpuModule) -> String {
+        let bindings = module.bindings;
+        format!(
+            "@group({}) @binding({}) var<storage, read> {}: array<u32>;\n\
+             @group({}) @binding({}) var<storage, read_write> {}: array<u32>;\n\n",
+            bindings.group,
+            bindings.buf,
+            module.buf_name(),
+            bindings.group,
+            bindings.dst_buf,
+            module.dst_buf_name(),
+        )
+    }
+
+    fn ref_alias(&self, name: &str) -> String {
+        format!("alias {}Ref = u32;\n", name)
+    }
+
+    fn uint_literal(&self, value: usize) -> String {
+        format!("{}u", value)
+    }
+
+    fn scalar_typename(&self, scalar: GpuScalar) -> &'static str {
+        match scalar {
+            GpuScalar::F32 => "f32",
+            GpuScalar::I32 => "i32",
+            GpuScalar::Bool => "bool",
+            _ => "u32",
+        }
+    }
+
+    fn vector_typename(&self, scalar: GpuScalar, size: usize) -> String {
+        let base = self.scalar_typename(scalar);
+        if size == 1 {
+            return base.into();
+        }
+        format!("vec{}<{}>", size, base)
+    }
+
+    fn bits_to_value(&self, scalar: GpuScalar, inner: &str, size: usize) -> String {
+        match scalar {
+            GpuScalar::F16 => format!("unpack2x16float({}).x", inner),
+            GpuScalar::F32 | GpuScalar::I32 => {
+                format!("bitcast<{}>({})", self.vector_typename(scalar, size), inner)
+            }
+            // Small signed values are sign-extended to int by their extractor.
+            _ => inner.into(),
+        }
+    }
+
+    fn value_to_bits(&self, scalar: GpuScalar, inner: &str, size: usize) -> String {
+        let uint_vec = self.vector_typename(GpuScalar::U32, size);
+        match scalar {
+            GpuScalar::F16 => format!("pack2x16float(vec2<f32>({}, 0.0))", inner),
+            GpuScalar::F32 | GpuScalar::I32 => format!("bitcast<{}>({})", uint_vec, inner),
+            // Small unsigned values are already unpacked to u32.
+            GpuScalar::U8 | GpuScalar::U16 => inner.into(),
+            _ => format!("{}({})", uint_vec, inner),
+        }
+    }
+
+    fn affine_typename(&self) -> &'static str {
+        "mat3x2<f32>"
+    }
+}
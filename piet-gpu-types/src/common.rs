@@ -0,0 +1,28 @@
+pub use self::common::{BBox, BBoxView, SRGBColor, SRGBColorView, SCHEMA_HASH};
+
+piet_gpu! {
+    #[rust_encode]
+    mod common {
+        struct BBox {
+            x0: u16,
+            x1: u16,
+            y0: u16,
+            y1: u16,
+        }
+
+        struct SRGBColor {
+            r: u8,
+            g: u8,
+            b: u8,
+            a: u8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn layout() {
+        super::check_layout_common().unwrap();
+    }
+}
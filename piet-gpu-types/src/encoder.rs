@@ -2,20 +2,36 @@
 
 //! New-style encoders (supporting proc macros)
 
+use std::fmt;
+
 pub struct A;
 
 /// A reference to an encoded object within a buffer
-#[derive(Clone, Copy, Debug)]
+#[derive(Debug)]
 pub struct Ref<T> {
     offset: u32,
     _phantom: std::marker::PhantomData<T>,
 }
 
+/// The offset stored for a `None` reference, as an `Option<Ref<T>>`.
+///
+/// Offset 0 is a valid location, so the shader code checks for this instead.
+pub const NULL_REF: u32 = 0xffff_ffff;
+
+/// A reference to a length-prefixed sequence of encoded objects within a buffer.
+///
+/// This is encoded as the length followed by the offset of the first element.
+#[derive(Debug)]
+pub struct Slice<T> {
+    len: u32,
+    offset: u32,
+    _phantom: std::marker::PhantomData<T>,
+}
+
 pub struct Encoder {
     buf: Vec<u8>,
 }
 
-// TODO: we probably do want to encode slices, get rid of Sized bound
 pub trait Encode: Sized {
     /// Size if it's a fixed-size object, otherwise 0.
     fn fixed_size() -> usize;
@@ -23,18 +39,81 @@ pub trait Encode: Sized {
     /// Encoded size, for both fixed and variable sized objects.
     fn encoded_size(&self) -> usize { Self::fixed_size() }
 
+    /// Alignment in bytes, a power of two of at least 4.
+    fn alignment() -> usize { 4 }
+
     /// Encode into a buffer; panics if not appropriately sized.
     fn encode_to(&self, buf: &mut [u8]);
 
     /// Allocate a chunk and encode, returning a reference.
     fn encode(&self, encoder: &mut Encoder) -> Ref<Self> {
         let size = self.encoded_size();
-        let (offset, buf) = encoder.alloc_chunk(size as u32);
+        let (offset, buf) = encoder.alloc_aligned_chunk(size as u32, Self::alignment() as u32);
         self.encode_to(buf);
         Ref::new(offset)
     }
 }
 
+// Implemented by hand so that `Ref<T>` is `Copy` regardless of `T`.
+impl<T> Clone for Ref<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Ref<T> {}
+
+impl<T> PartialEq for Ref<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.offset == other.offset
+    }
+}
+
+/// An error decoding an object, from bytes that no value encodes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodeError {
+    /// The tag of an enum is none of its variants'.
+    UnknownTag { ty: &'static str, tag: u32 },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnknownTag { ty, tag } => write!(f, "unknown {} tag {}", ty, tag),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decoding of objects from a buffer, the inverse of `Encode`.
+pub trait Decode: Sized {
+    /// Decode from the start of a buffer; panics if the buffer is too short,
+    /// and fails for bytes that no value encodes, such as an unknown tag.
+    fn decode_from(buf: &[u8]) -> Result<Self, DecodeError>;
+
+    /// Decode the object referenced by `r`.
+    fn decode(buf: &[u8], r: Ref<Self>) -> Result<Self, DecodeError> {
+        Self::decode_from(&buf[r.offset() as usize..])
+    }
+}
+
+/// Types with a generated view, which reads an encoded object in place.
+///
+/// The accessors of a view mirror the shader functions, so `PietItem_tag` is
+/// `PietItemView::tag` and `BBox_unpack_x0` is `BBoxView::unpack_x0`.
+pub trait Viewable<'a>: Sized {
+    type View: Copy;
+
+    /// A view of the object at `offset` bytes into `buf`.
+    fn view_at(buf: &'a [u8], offset: u32) -> Self::View;
+
+    /// A view of the object referenced by `r`.
+    fn view(buf: &'a [u8], r: Ref<Self>) -> Self::View {
+        Self::view_at(buf, r.offset())
+    }
+}
+
 impl<T> Ref<T> {
     fn new(offset: u32) -> Ref<T> {
         Ref {
@@ -52,6 +131,63 @@ impl<T> Ref<T> {
     }
 }
 
+impl<T> Slice<T> {
+    fn new(len: u32, offset: u32) -> Slice<T> {
+        Slice {
+            len,
+            offset,
+            _phantom: Default::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+}
+
+impl<T: Encode> Slice<T> {
+    /// A reference to the element at index `ix`.
+    pub fn get(&self, ix: usize) -> Ref<T> {
+        assert!(ix < self.len(), "slice index out of bounds");
+        Ref::new(self.offset + (ix * T::fixed_size()) as u32)
+    }
+}
+
+impl<T: Encode + Decode> Slice<T> {
+    /// Decode all the elements.
+    pub fn decode_all(&self, buf: &[u8]) -> Result<Vec<T>, DecodeError> {
+        (0..self.len()).map(|ix| T::decode(buf, self.get(ix))).collect()
+    }
+}
+
+impl<T> Clone for Slice<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Slice<T> {}
+
+impl<T> PartialEq for Slice<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.offset == other.offset
+    }
+}
+
+impl Default for Encoder {
+    fn default() -> Encoder {
+        Encoder::new()
+    }
+}
+
 impl Encoder {
     pub fn new() -> Encoder {
         Encoder {
@@ -65,6 +201,27 @@ impl Encoder {
         (offset as u32, &mut self.buf[offset..])
     }
 
+    /// Allocate a chunk starting at a multiple of `align` bytes.
+    pub fn alloc_aligned_chunk(&mut self, size: u32, align: u32) -> (u32, &mut [u8]) {
+        let offset = (self.buf.len() + align as usize - 1) & !(align as usize - 1);
+        self.buf.resize(offset, 0);
+        self.alloc_chunk(size)
+    }
+
+    /// Encode a sequence of fixed size objects, returning a slice.
+    ///
+    /// The chunk is padded to a multiple of 4 bytes so that following objects
+    /// stay aligned.
+    pub fn encode_slice<T: Encode>(&mut self, slice: &[T]) -> Slice<T> {
+        let size = T::fixed_size();
+        let padded = (slice.len() * size + 3) & !3;
+        let (offset, buf) = self.alloc_aligned_chunk(padded as u32, T::alignment() as u32);
+        for (ix, val) in slice.iter().enumerate() {
+            val.encode_to(&mut buf[ix * size..]);
+        }
+        Slice::new(slice.len() as u32, offset)
+    }
+
     pub fn buf(&self) -> &[u8] {
         &self.buf
     }
@@ -84,6 +241,49 @@ impl<T> Encode for Ref<T> {
     }
 }
 
+impl<T> Decode for Ref<T> {
+    fn decode_from(buf: &[u8]) -> Result<Self, DecodeError> {
+        Ok(Ref::new(u32::decode_from(buf)?))
+    }
+}
+
+impl<T> Encode for Option<Ref<T>> {
+    fn fixed_size() -> usize {
+        4
+    }
+
+    fn encode_to(&self, buf: &mut [u8]) {
+        let offset = self.map_or(NULL_REF, |r| r.offset);
+        buf[0..4].copy_from_slice(&offset.to_le_bytes());
+    }
+}
+
+impl<T> Decode for Option<Ref<T>> {
+    fn decode_from(buf: &[u8]) -> Result<Self, DecodeError> {
+        match u32::decode_from(buf)? {
+            NULL_REF => Ok(None),
+            offset => Ok(Some(Ref::new(offset))),
+        }
+    }
+}
+
+impl<T> Encode for Slice<T> {
+    fn fixed_size() -> usize {
+        8
+    }
+
+    fn encode_to(&self, buf: &mut [u8]) {
+        buf[0..4].copy_from_slice(&self.len.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.offset.to_le_bytes());
+    }
+}
+
+impl<T> Decode for Slice<T> {
+    fn decode_from(buf: &[u8]) -> Result<Self, DecodeError> {
+        Ok(Slice::new(u32::decode_from(buf)?, u32::decode_from(&buf[4..])?))
+    }
+}
+
 // Encode impls for scalar and small vector types are as needed; it's a finite set of
 // possibilities, so we could do it all with macros, but by hand is expedient.
 
@@ -97,6 +297,12 @@ impl Encode for u32 {
     }
 }
 
+impl Decode for u32 {
+    fn decode_from(buf: &[u8]) -> Result<Self, DecodeError> {
+        Ok(u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]))
+    }
+}
+
 impl Encode for f32 {
     fn fixed_size() -> usize {
         4
@@ -107,6 +313,12 @@ impl Encode for f32 {
     }
 }
 
+impl Decode for f32 {
+    fn decode_from(buf: &[u8]) -> Result<Self, DecodeError> {
+        Ok(f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]))
+    }
+}
+
 impl Encode for [u16; 4] {
     fn fixed_size() -> usize {
         8
@@ -120,6 +332,96 @@ impl Encode for [u16; 4] {
     }
 }
 
+// The remaining scalars are mostly needed as slice elements.
+macro_rules! impl_scalar {
+    ($ty:ty, $size:expr) => {
+        impl Encode for $ty {
+            fn fixed_size() -> usize {
+                $size
+            }
+
+            fn encode_to(&self, buf: &mut [u8]) {
+                buf[0..$size].copy_from_slice(&self.to_le_bytes());
+            }
+        }
+
+        impl Decode for $ty {
+            fn decode_from(buf: &[u8]) -> Result<Self, DecodeError> {
+                let mut bytes = [0; $size];
+                bytes.copy_from_slice(&buf[0..$size]);
+                Ok(<$ty>::from_le_bytes(bytes))
+            }
+        }
+    };
+}
+
+impl_scalar!(u8, 1);
+impl_scalar!(u16, 2);
+impl_scalar!(i8, 1);
+impl_scalar!(i16, 2);
+impl_scalar!(i32, 4);
+#[cfg(feature = "half")]
+impl_scalar!(half::f16, 2);
+
+/// The Rust type of `f16` fields: `half::f16` with the `half` feature, and
+/// otherwise its bits, as from `half::f16::to_bits`.
+#[cfg(feature = "half")]
+pub type F16 = half::f16;
+#[cfg(not(feature = "half"))]
+pub type F16 = u16;
+
+/// The value of a bit field, stored in the low bits of a `u32`.
+pub trait BitField: Copy {
+    /// The low `bits` bits of the value.
+    fn to_bits(&self, bits: u32) -> u32;
+
+    /// The value of the low `bits` bits of `word`, sign-extended for signed types.
+    fn from_bits(word: u32, bits: u32) -> Self;
+}
+
+macro_rules! impl_bit_field {
+    ($ty:ty, $signed:ty) => {
+        impl BitField for $ty {
+            fn to_bits(&self, bits: u32) -> u32 {
+                (*self as u32) & (u32::MAX >> (32 - bits))
+            }
+
+            fn from_bits(word: u32, bits: u32) -> Self {
+                // Shift the field to the top of the word and back to extend it.
+                ((word << (32 - bits)) as $signed >> (32 - bits)) as $ty
+            }
+        }
+    };
+}
+
+impl_bit_field!(u8, u32);
+impl_bit_field!(u16, u32);
+impl_bit_field!(u32, u32);
+impl_bit_field!(i8, i32);
+impl_bit_field!(i16, i32);
+impl_bit_field!(i32, i32);
+
+impl BitField for bool {
+    fn to_bits(&self, _bits: u32) -> u32 {
+        *self as u32
+    }
+
+    fn from_bits(word: u32, _bits: u32) -> Self {
+        word & 1 != 0
+    }
+}
+
+impl Decode for [u16; 4] {
+    fn decode_from(buf: &[u8]) -> Result<Self, DecodeError> {
+        Ok([
+            u16::from_le_bytes([buf[0], buf[1]]),
+            u16::from_le_bytes([buf[2], buf[3]]),
+            u16::from_le_bytes([buf[4], buf[5]]),
+            u16::from_le_bytes([buf[6], buf[7]]),
+        ])
+    }
+}
+
 impl Encode for [f32; 2] {
     fn fixed_size() -> usize {
         8
@@ -131,9 +433,64 @@ impl Encode for [f32; 2] {
     }
 }
 
-// TODO: make this work for slices too, but need to deal with Sized bound
-//
-// Note: only works for vectors of fixed size objects.
+impl Decode for [f32; 2] {
+    fn decode_from(buf: &[u8]) -> Result<Self, DecodeError> {
+        Ok([f32::decode_from(buf)?, f32::decode_from(&buf[4..])?])
+    }
+}
+
+/// A 2D affine transform, the `Affine2` type of a schema.
+///
+/// The coefficients are in the order of `kurbo::Affine`, so that the shader
+/// sees three `float2` columns.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Affine2(pub [f32; 6]);
+
+impl Affine2 {
+    pub const IDENTITY: Affine2 = Affine2([1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+}
+
+impl From<[f64; 6]> for Affine2 {
+    fn from(coeffs: [f64; 6]) -> Affine2 {
+        let mut result = [0.0; 6];
+        for (dst, src) in result.iter_mut().zip(coeffs.iter()) {
+            *dst = *src as f32;
+        }
+        Affine2(result)
+    }
+}
+
+#[cfg(feature = "kurbo")]
+impl From<kurbo::Affine> for Affine2 {
+    fn from(affine: kurbo::Affine) -> Affine2 {
+        Affine2::from(affine.as_coeffs())
+    }
+}
+
+impl Encode for Affine2 {
+    fn fixed_size() -> usize {
+        24
+    }
+
+    fn encode_to(&self, buf: &mut [u8]) {
+        for (ix, coeff) in self.0.iter().enumerate() {
+            coeff.encode_to(&mut buf[ix * 4..]);
+        }
+    }
+}
+
+impl Decode for Affine2 {
+    fn decode_from(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut result = [0.0; 6];
+        for (ix, coeff) in result.iter_mut().enumerate() {
+            *coeff = f32::decode_from(&buf[ix * 4..])?;
+        }
+        Ok(Affine2(result))
+    }
+}
+
+// Note: only works for vectors of fixed size objects, and doesn't record the
+// length; use `Encoder::encode_slice` for a length-prefixed sequence.
 impl<T: Encode> Encode for Vec<T> {
     fn fixed_size() -> usize { 0 }
     fn encoded_size(&self) -> usize {
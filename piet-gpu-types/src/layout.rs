@@ -0,0 +1,303 @@
+//  Copyright 2020 The xi-editor authors.
+
+//! Descriptions of the memory layout of `piet_gpu!` modules.
+//!
+//! These are generated as `layout_<module>()` and describe the same layout as
+//! the generated shader code, for tools that need to inspect GPU buffers, and
+//! for the `check_layout` of modules with Rust types.
+
+use std::fmt::Write;
+
+use crate::encoder::Encode;
+
+/// The layout of all the types in a module.
+#[derive(Clone, Debug)]
+pub struct ModuleLayout {
+    pub name: &'static str,
+    pub types: Vec<TypeLayout>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TypeLayout {
+    pub name: &'static str,
+    /// Size in bytes, including the tag of enum variants.
+    pub size: usize,
+    pub alignment: usize,
+    pub kind: TypeKind,
+}
+
+#[derive(Clone, Debug)]
+pub enum TypeKind {
+    Struct {
+        /// Whether the struct starts with the tag of an enum variant.
+        has_tag: bool,
+        packed_fields: Vec<PackedFieldLayout>,
+    },
+    Enum {
+        /// Size of the tag in bytes; smaller tags share their word with the
+        /// fields of the variant.
+        tag_size: usize,
+        variants: Vec<VariantLayout>,
+    },
+}
+
+/// A field as stored in the buffer, which may hold several sub-word fields.
+#[derive(Clone, Debug)]
+pub struct PackedFieldLayout {
+    pub name: &'static str,
+    /// Byte offset from the start of the struct.
+    pub offset: usize,
+    pub size: usize,
+    pub fields: Vec<FieldLayout>,
+}
+
+/// A field as declared in the schema.
+#[derive(Clone, Debug)]
+pub struct FieldLayout {
+    pub name: &'static str,
+    pub ty: &'static str,
+    /// Bit offset within the packed field.
+    pub bit_offset: usize,
+    pub size: usize,
+    /// Size in bits, which is less than `8 * size` for bit fields.
+    pub bit_size: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct VariantLayout {
+    pub name: &'static str,
+    pub tag: u32,
+    /// The struct describing the variant, which includes the tag; unit
+    /// variants have none.
+    pub body: Option<&'static str>,
+    /// Byte offset of the first field of the body.
+    pub body_offset: usize,
+}
+
+impl ModuleLayout {
+    /// Find the layout of a type by name.
+    pub fn get(&self, name: &str) -> Option<&TypeLayout> {
+        self.types.iter().find(|ty| ty.name == name)
+    }
+
+    /// The bit offset of `field` of the struct `ty`, which is stored in
+    /// `packed_field`.
+    pub fn field_bit_offset(&self, ty: &str, packed_field: &str, field: &str) -> Option<usize> {
+        let packed_fields = match &self.get(ty)?.kind {
+            TypeKind::Struct { packed_fields, .. } => packed_fields,
+            TypeKind::Enum { .. } => return None,
+        };
+        let packed_field = packed_fields.iter().find(|pf| pf.name == packed_field)?;
+        let field = packed_field.fields.iter().find(|f| f.name == field)?;
+        Some(packed_field.offset * 8 + field.bit_offset)
+    }
+
+    /// Serialize to JSON.
+    ///
+    /// All strings are identifiers or type names, so no escaping is needed.
+    pub fn to_json(&self) -> String {
+        let mut r = String::new();
+        write!(r, "{{\"name\":\"{}\",\"types\":[", self.name).unwrap();
+        for (i, ty) in self.types.iter().enumerate() {
+            if i > 0 {
+                r.push(',');
+            }
+            ty.write_json(&mut r);
+        }
+        r.push_str("]}");
+        r
+    }
+}
+
+impl TypeLayout {
+    fn write_json(&self, r: &mut String) {
+        write!(
+            r,
+            "{{\"name\":\"{}\",\"size\":{},\"alignment\":{},",
+            self.name, self.size, self.alignment
+        )
+        .unwrap();
+        match &self.kind {
+            TypeKind::Struct {
+                has_tag,
+                packed_fields,
+            } => {
+                write!(r, "\"kind\":\"struct\",\"has_tag\":{},\"packed_fields\":[", has_tag)
+                    .unwrap();
+                for (i, pf) in packed_fields.iter().enumerate() {
+                    if i > 0 {
+                        r.push(',');
+                    }
+                    write!(
+                        r,
+                        "{{\"name\":\"{}\",\"offset\":{},\"size\":{},\"fields\":[",
+                        pf.name, pf.offset, pf.size
+                    )
+                    .unwrap();
+                    for (j, f) in pf.fields.iter().enumerate() {
+                        if j > 0 {
+                            r.push(',');
+                        }
+                        write!(
+                            r,
+                            "{{\"name\":\"{}\",\"ty\":\"{}\",\"bit_offset\":{},\"size\":{},\"bit_size\":{}}}",
+                            f.name, f.ty, f.bit_offset, f.size, f.bit_size
+                        )
+                        .unwrap();
+                    }
+                    r.push_str("]}");
+                }
+                r.push(']');
+            }
+            TypeKind::Enum { tag_size, variants } => {
+                write!(r, "\"kind\":\"enum\",\"tag_size\":{},\"variants\":[", tag_size).unwrap();
+                for (i, v) in variants.iter().enumerate() {
+                    if i > 0 {
+                        r.push(',');
+                    }
+                    let body = match v.body {
+                        Some(body) => format!("\"{}\"", body),
+                        None => "null".into(),
+                    };
+                    write!(
+                        r,
+                        "{{\"name\":\"{}\",\"tag\":{},\"body\":{},\"body_offset\":{}}}",
+                        v.name, v.tag, body, v.body_offset
+                    )
+                    .unwrap();
+                }
+                r.push(']');
+            }
+        }
+        r.push('}');
+    }
+}
+
+/// The first bit at which the encodings of `a` and `b` differ.
+pub fn encoded_bit_offset<T: Encode>(a: &T, b: &T) -> Option<usize> {
+    let encode = |value: &T| {
+        let mut buf = vec![0; T::fixed_size()];
+        value.encode_to(&mut buf);
+        buf
+    };
+    let (a, b) = (encode(a), encode(b));
+    let ix = a.iter().zip(&b).position(|(a, b)| a != b)?;
+    Some(ix * 8 + (a[ix] ^ b[ix]).trailing_zeros() as usize)
+}
+
+/// The bit offset of an inline struct in the encodings of `a` and `b`, which
+/// hold `field_a` and `field_b` there. The struct's first field may follow a
+/// tag, so that isn't the first bit at which they differ.
+pub fn encoded_field_offset<T: Encode, F: Encode>(
+    a: &T,
+    b: &T,
+    field_a: &F,
+    field_b: &F,
+) -> Option<usize> {
+    encoded_bit_offset(a, b)?.checked_sub(encoded_bit_offset(field_a, field_b)?)
+}
+
+/// Check that `field` of `ty`, stored in `packed_field`, is encoded at
+/// `bit_offset`, which is where `layout` says the shaders read it from.
+pub fn check_field_offset(
+    layout: &ModuleLayout,
+    ty: &str,
+    packed_field: &str,
+    field: &str,
+    bit_offset: Option<usize>,
+) -> Result<(), String> {
+    let read_bit_offset = layout
+        .field_bit_offset(ty, packed_field, field)
+        .ok_or_else(|| format!("no layout for {}.{}", ty, field))?;
+    match bit_offset {
+        Some(bit_offset) if bit_offset == read_bit_offset => Ok(()),
+        Some(bit_offset) => Err(format!(
+            "{}.{} is encoded at bit {} but read from bit {}",
+            ty, field, bit_offset, read_bit_offset
+        )),
+        None => Err(format!("{}.{} is not encoded", ty, field)),
+    }
+}
+
+/// Find the schema hash of a module in generated shader code, where it is
+/// given as `<MODULE>_SCHEMA_HASH_LO` and `<MODULE>_SCHEMA_HASH_HI`.
+pub fn shader_schema_hash(source: &str, module: &str) -> Option<u64> {
+    let half = |suffix: &str| {
+        let name = format!("{}_SCHEMA_HASH_{}", module.to_uppercase(), suffix);
+        let line = source.lines().find(|line| {
+            line.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .any(|word| word == name)
+        })?;
+        let literal = line.trim().trim_end_matches(';').rsplit(' ').next()?;
+        let hex = literal.trim_start_matches("0x").trim_end_matches('u');
+        u32::from_str_radix(hex, 16).ok()
+    };
+    Some(u64::from(half("HI")?) << 32 | u64::from(half("LO")?))
+}
+
+/// Check that shader code was generated from the same layout as the Rust
+/// encoder, whose `SCHEMA_HASH` is `expected`.
+pub fn check_schema_hash(source: &str, module: &str, expected: u64) -> Result<(), String> {
+    match shader_schema_hash(source, module) {
+        Some(hash) if hash == expected => Ok(()),
+        Some(hash) => Err(format!(
+            "shader code for `{}` is stale: its schema hash is {:#018x}, but the encoder's is {:#018x}",
+            module, hash, expected
+        )),
+        None => Err(format!("shader code has no schema hash for `{}`", module)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    piet_gpu! {
+        #[rust_encode]
+        mod transforms {
+            struct Placement {
+                mat: Affine2,
+                depth: u16,
+            }
+            struct Transform {
+                mat: Affine2,
+                scale: f32,
+            }
+            enum Op {
+                Nop,
+                Transform(Transform),
+                Compose { a: Affine2, b: Affine2 },
+            }
+        }
+    }
+
+    #[test]
+    fn affine_layout() {
+        check_layout_transforms().unwrap();
+        // The matrix is only word aligned, so nothing pads the structs.
+        assert_eq!(transforms::Placement::fixed_size(), 28);
+        // The tag of `Op::Transform` comes first, right before the matrix.
+        assert_eq!(transforms::Transform::fixed_size(), 32);
+    }
+
+    #[test]
+    fn field_offsets() {
+        let layout = layout_transforms();
+        assert_eq!(layout.field_bit_offset("Placement", "depth", "depth"), Some(192));
+        assert_eq!(layout.field_bit_offset("Op", "depth", "depth"), None);
+        check_field_offset(&layout, "Placement", "depth", "depth", Some(192)).unwrap();
+        let err = check_field_offset(&layout, "Placement", "depth", "depth", Some(0));
+        assert_eq!(err.unwrap_err(), "Placement.depth is encoded at bit 0 but read from bit 192");
+    }
+
+    #[test]
+    fn schema_hash_in_shaders() {
+        for lang in &["HLSL", "MSL", "GLSL", "WGSL"] {
+            let source = crate::scene::gen_gpu_scene(lang);
+            assert_eq!(shader_schema_hash(&source, "scene"), Some(crate::scene::SCHEMA_HASH));
+            assert!(check_schema_hash(&source, "scene", crate::common::SCHEMA_HASH).is_err());
+            assert!(shader_schema_hash(&source, "common").is_none());
+        }
+        assert_eq!(crate::scene::SCENE_SCHEMA_HASH, crate::scene::SCHEMA_HASH);
+    }
+}
@@ -1,5 +1,10 @@
 #[macro_use]
 extern crate piet_gpu_derive;
 
+pub mod common;
 pub mod encoder;
+pub mod layout;
 pub mod scene;
+
+#[cfg(test)]
+mod tests;
@@ -1,8 +1,18 @@
-#[macro_use]
-extern crate piet_gpu_derive;
-
-use piet_gpu_types::encoder::{Encode, Encoder};
-
 fn main() {
-    print!("{}", piet_gpu_types::scene::gen_gpu_scene("HLSL"));
+    let lang = std::env::args().nth(1).unwrap_or_else(|| "HLSL".into());
+    if lang == "LAYOUT" {
+        println!("{}", piet_gpu_types::scene::layout_scene().to_json());
+        return;
+    }
+    if lang == "CHECK" {
+        match piet_gpu_types::scene::check_layout_scene() {
+            Ok(()) => println!("layout ok"),
+            Err(e) => {
+                eprintln!("layout mismatch: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    print!("{}", piet_gpu_types::scene::gen_gpu_scene(&lang));
 }
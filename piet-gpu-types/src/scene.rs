@@ -1,21 +1,12 @@
-pub use self::scene::{BBox, SRGBColor, PietCircle, PietGlyph, PietItem};
+pub use crate::common::{BBox, BBoxView, SRGBColor, SRGBColorView};
+pub use self::scene::{
+    PietCircle, PietCircleView, PietGlyph, PietGlyphView, PietItem, PietItemView, SCHEMA_HASH,
+};
 
 piet_gpu! {
     #[rust_encode]
     mod scene {
-        struct BBox {
-            x0: u16,
-            x1: u16,
-            y0: u16,
-            y1: u16,
-        }
-
-        struct SRGBColor {
-            r: u8,
-            g: u8,
-            b: u8,
-            a: u8,
-        }
+        use crate::common::{BBox, SRGBColor};
 
         struct PietGlyph {
             scene_bbox: BBox,
@@ -29,8 +20,16 @@ piet_gpu! {
         }
 
         enum PietItem {
-            Circle(PietCircle),
-            Glyph(PietGlyph),
+            Circle(PietCircle) = 0,
+            Glyph(PietGlyph) = 1,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn layout() {
+        super::check_layout_scene().unwrap();
+    }
+}
@@ -0,0 +1,80 @@
+use crate::encoder::{Decode, Encode, Encoder};
+
+piet_gpu! {
+    #[rust_encode]
+    #[layout(std430)]
+    mod aligned {
+        struct Vec3 {
+            v: [f32; 3],
+        }
+        #[align(16)]
+        struct Particle {
+            id: u8,
+            pos: [f32; 3],
+            #[pack(none)]
+            flags: u8,
+            kind: u16,
+            vel: [f32; 2],
+            home: Vec3,
+            #[align(8)]
+            r: u32,
+        }
+        #[pack(none)]
+        struct Loose {
+            a: u8,
+            b: u8,
+            c: [u16; 2],
+        }
+        enum Shape {
+            Dot(Particle),
+            Pair { a: u8, b: [f32; 4] },
+            Empty,
+        }
+    }
+}
+
+use self::aligned::*;
+
+#[test]
+fn layout() {
+    check_layout_aligned().unwrap();
+    let layout = layout_aligned();
+    let size_align = |name| {
+        let ty = layout.get(name).unwrap();
+        (ty.size, ty.alignment)
+    };
+    assert_eq!(size_align("Vec3"), (16, 16));
+    assert_eq!(size_align("Particle"), (80, 16));
+    assert_eq!(size_align("Loose"), (12, 4));
+    assert_eq!((Particle::fixed_size(), Particle::alignment()), (80, 16));
+    assert_eq!((Shape::fixed_size(), Shape::alignment()), (80, 16));
+}
+
+#[test]
+fn aligned_encoding() {
+    let mut e = Encoder::new();
+    // Pad the buffer so that the encoder has to align the particle.
+    Loose { a: 1, b: 2, c: [3, 4] }.encode(&mut e);
+    let particle = Particle {
+        id: 7,
+        pos: [1.0, 2.0, 3.0],
+        flags: 9,
+        kind: 300,
+        vel: [4.0, 5.0],
+        home: Vec3 { v: [8.0, 9.0, 10.0] },
+        r: 11,
+    };
+    let r = particle.encode(&mut e);
+    assert_eq!(r.offset() % 16, 0);
+    assert_eq!(Particle::decode(e.buf(), r).unwrap(), particle);
+    let shapes = [
+        Shape::Dot(particle),
+        Shape::Pair { a: 5, b: [1.0, 2.0, 3.0, 4.0] },
+        Shape::Empty,
+    ];
+    for shape in &shapes {
+        let r = shape.encode(&mut e);
+        assert_eq!(r.offset() % 16, 0);
+        assert_eq!(&Shape::decode(e.buf(), r).unwrap(), shape);
+    }
+}
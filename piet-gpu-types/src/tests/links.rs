@@ -0,0 +1,55 @@
+use crate::encoder::{Decode, Encode, Encoder, Viewable, NULL_REF};
+
+piet_gpu! {
+    #[rust_encode]
+    mod links {
+        struct Clip {
+            parent: Option<Ref<Clip>>,
+            depth: u32,
+        }
+        struct Seg {
+            next: Option<Ref<Seg>>,
+            clips: [Option<Ref<Clip>>; 2],
+            alts: [Option<Ref<Seg>>],
+        }
+        enum Node {
+            Leaf,
+            Link { to: Option<Ref<Node>>, w: u16 },
+        }
+    }
+}
+
+use self::links::*;
+
+#[test]
+fn layout() {
+    check_layout_links().unwrap();
+}
+
+#[test]
+fn nullable_refs() {
+    let mut e = Encoder::new();
+    let root = Clip { parent: None, depth: 0 }.encode(&mut e);
+    // A reference to offset 0 is still a reference.
+    assert_eq!(root.offset(), 0);
+    let child = Clip { parent: Some(root), depth: 1 };
+    let child_ref = child.encode(&mut e);
+    assert_eq!(&e.buf()[0..4], &NULL_REF.to_le_bytes());
+    assert_eq!(Clip::decode(e.buf(), root).unwrap().parent, None);
+    assert_eq!(Clip::decode(e.buf(), child_ref).unwrap(), child);
+
+    let alts = e.encode_slice(&[None, Some(root.transmute::<Seg>())]);
+    let seg = Seg { next: None, clips: [Some(child_ref), None], alts };
+    let seg_ref = seg.encode(&mut e);
+    assert_eq!(Seg::decode(e.buf(), seg_ref).unwrap(), seg);
+    assert_eq!(seg.alts.decode_all(e.buf()).unwrap(), vec![None, Some(root.transmute())]);
+    let node = Node::Link { to: None, w: 7 };
+    let node_ref = node.encode(&mut e);
+    assert_eq!(Node::decode(e.buf(), node_ref).unwrap(), node);
+
+    let view = Seg::view(e.buf(), seg_ref);
+    assert_eq!(view.next(), None);
+    assert_eq!((view.clips(0), view.clips(1)), (Some(child_ref), None));
+    assert_eq!(view.alts_index(1), Some(root.transmute()));
+    assert_eq!(Clip::view(e.buf(), child_ref).parent(), Some(root));
+}
@@ -0,0 +1,8 @@
+//! Encode and decode tests of the generated Rust types, by feature.
+
+mod roundtrip;
+mod tags;
+mod links;
+mod views;
+mod aligned;
+mod packed;
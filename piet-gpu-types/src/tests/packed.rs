@@ -0,0 +1,79 @@
+use crate::encoder::{Decode, Encode, Encoder};
+
+piet_gpu! {
+    #[rust_encode]
+    mod packed {
+        struct Flags {
+            on: bool,
+            #[bits(4)]
+            level: u8,
+            #[bits(12)]
+            count: u16,
+            #[bits(5)]
+            delta: i8,
+            off: bool,
+            tail: u16,
+        }
+        struct Wide {
+            #[bits(20)]
+            a: u32,
+            #[bits(20)]
+            b: i32,
+            c: bool,
+        }
+        struct Color {
+            #[unorm]
+            rgba: [u8; 4],
+            #[unorm(u16)]
+            depth: f32,
+            #[snorm]
+            normal: [i8; 2],
+        }
+    }
+}
+
+use self::packed::*;
+
+#[test]
+fn layout() {
+    check_layout_packed().unwrap();
+}
+
+#[test]
+fn bit_fields() {
+    let flags = Flags { on: true, level: 9, count: 0xabc, delta: -3, off: false, tail: 0x1234 };
+    let mut e = Encoder::new();
+    let r = flags.encode(&mut e);
+    // on | level << 1 | count << 5 | (delta & 0x1f) << 17, then tail in the next word.
+    assert_eq!(e.buf(), &[0x93, 0x57, 0x3b, 0, 0x34, 0x12, 0, 0]);
+    assert_eq!(Flags::decode(e.buf(), r).unwrap(), flags);
+    // A field that doesn't fit in what is left of a word starts the next one.
+    let wide = Wide { a: 0xfedcb, b: -0x7ffff, c: true };
+    let r = wide.encode(&mut e);
+    assert_eq!(&e.buf()[8..], &[0xcb, 0xed, 0x0f, 0, 0x01, 0, 0x18, 0]);
+    assert_eq!(Wide::decode(e.buf(), r).unwrap(), wide);
+}
+
+#[test]
+fn bit_field_overflow() {
+    // Values are cut to the width of their field.
+    let mut e = Encoder::new();
+    let flags = Flags { on: false, level: 0x1f, count: 0xf001, delta: 17, off: true, tail: 0 };
+    let r = flags.encode(&mut e);
+    let decoded = Flags::decode(e.buf(), r).unwrap();
+    assert_eq!((decoded.level, decoded.count, decoded.delta), (0xf, 0x001, -15));
+    assert!(!decoded.on && decoded.off);
+}
+
+#[test]
+fn normalized() {
+    let color = Color { rgba: [0.0, 1.0, 2.0, 0.5], depth: 0.25, normal: [-1.5, 0.5] };
+    let mut e = Encoder::new();
+    let r = color.encode(&mut e);
+    // Values are clamped to their range and rounded.
+    assert_eq!(e.buf(), &[0, 255, 255, 128, 0x00, 0x40, 0x81, 0x40]);
+    let decoded = Color::decode(e.buf(), r).unwrap();
+    assert_eq!(decoded.rgba, [0.0, 1.0, 1.0, 128.0 / 255.0]);
+    assert_eq!(decoded.depth, 16384.0 / 65535.0);
+    assert_eq!(decoded.normal, [-1.0, 64.0 / 127.0]);
+}
@@ -0,0 +1,148 @@
+use crate::encoder::{Decode, Encode, Encoder, F16};
+
+piet_gpu! {
+    #[rust_encode]
+    mod roundtrip {
+        struct Inner {
+            x: u32,
+            y: [u16; 2],
+        }
+        struct Mixed {
+            a: f32,
+            b: i32,
+            c: [f32; 2],
+            d: [u8; 4],
+            e: u16,
+            f: [i32; 3],
+            g: Ref<Inner>,
+            i: Inner,
+        }
+        struct Signed {
+            a: i8,
+            b: [i16; 2],
+            c: [i8; 3],
+            d: i16,
+        }
+        struct Half {
+            r: f16,
+            s: [f16; 2],
+            t: [f16; 4],
+        }
+        struct Ramp {
+            id: u32,
+            stops: [f32],
+            inners: [Inner],
+            deltas: [i8],
+        }
+        struct Quad {
+            head: u16,
+            corners: [[f32; 2]; 4],
+            boxes: [Inner; 2],
+            tail: u16,
+        }
+        enum Choice {
+            Nothing,
+            First(Inner),
+            Line(Inner, Inner, u16),
+            Rect { bbox: Inner, radius: f32, flags: u8 },
+        }
+    }
+}
+
+use self::roundtrip::*;
+
+fn inner() -> Inner {
+    Inner { x: 7, y: [9, 65000] }
+}
+
+#[test]
+fn layout() {
+    check_layout_roundtrip().unwrap();
+}
+
+#[test]
+fn structs() {
+    let mut e = Encoder::new();
+    let r = inner().encode(&mut e);
+    let mixed = Mixed {
+        a: 1.5,
+        b: -3,
+        c: [2.0, -4.0],
+        d: [1, 2, 3, 4],
+        e: 513,
+        f: [-1, 2, -3],
+        g: r,
+        i: inner(),
+    };
+    let signed = Signed { a: -128, b: [-1, 32767], c: [-2, 0, 127], d: -300 };
+    // 1.0, -2.0, the smallest subnormal and the largest finite value, as f16 bits.
+    let f16 = |bits: u16| F16::from_le_bytes(bits.to_le_bytes());
+    let half = Half {
+        r: f16(0x3c00),
+        s: [f16(0xc000), f16(0x0001)],
+        t: [0, 1, 2, 0x7bff].map(f16),
+    };
+    let (mr, sr, hr) = (mixed.encode(&mut e), signed.encode(&mut e), half.encode(&mut e));
+    assert_eq!(Inner::decode(e.buf(), r).unwrap(), inner());
+    assert_eq!(Mixed::decode(e.buf(), mr).unwrap(), mixed);
+    assert_eq!(Signed::decode(e.buf(), sr).unwrap(), signed);
+    assert_eq!(Half::decode(e.buf(), hr).unwrap(), half);
+}
+
+#[cfg(feature = "half")]
+#[test]
+fn half_floats() {
+    use half::f16;
+    let mut e = Encoder::new();
+    let half = Half {
+        r: f16::from_f32(1.0),
+        s: [f16::from_f32(-2.0), f16::MIN_POSITIVE_SUBNORMAL],
+        t: [f16::ZERO, f16::ONE, f16::NEG_INFINITY, f16::MAX],
+    };
+    let r = half.encode(&mut e);
+    // `r` has a word to itself, and the vectors fill theirs from the low half.
+    let bytes = [0, 0x3c, 0, 0, 0, 0xc0, 1, 0, 0, 0, 0, 0x3c, 0, 0xfc, 0xff, 0x7b];
+    assert_eq!(&e.buf()[r.offset() as usize..][..16], &bytes);
+    assert_eq!(Half::decode(e.buf(), r).unwrap(), half);
+}
+
+#[test]
+fn slices_and_arrays() {
+    let mut e = Encoder::new();
+    let stops = e.encode_slice(&[0.0f32, 0.5, 1.0]);
+    let inners = e.encode_slice(&[inner(), Inner { x: 1, y: [2, 3] }]);
+    let deltas = e.encode_slice(&[-1i8, 2, -128]);
+    let ramp = Ramp { id: 3, stops, inners, deltas };
+    let rr = ramp.encode(&mut e);
+    let decoded = Ramp::decode(e.buf(), rr).unwrap();
+    assert_eq!(decoded, ramp);
+    assert_eq!(decoded.stops.decode_all(e.buf()).unwrap(), vec![0.0, 0.5, 1.0]);
+    assert_eq!(decoded.inners.decode_all(e.buf()).unwrap()[1], Inner { x: 1, y: [2, 3] });
+    assert_eq!(decoded.deltas.decode_all(e.buf()).unwrap(), vec![-1, 2, -128]);
+    let quad = Quad {
+        head: 5,
+        corners: [[1.0, 2.0], [3.0, 4.0], [5.0, 6.0], [7.0, 8.0]],
+        boxes: [inner(), Inner { x: 1, y: [2, 3] }],
+        tail: 9,
+    };
+    let qr = quad.encode(&mut e);
+    assert_eq!(Quad::decode(e.buf(), qr).unwrap(), quad);
+}
+
+#[test]
+fn enum_variants() {
+    let mut e = Encoder::new();
+    let choices = [
+        Choice::Nothing,
+        Choice::First(inner()),
+        Choice::Line(inner(), Inner { x: 1, y: [2, 3] }, 77),
+        Choice::Rect { bbox: inner(), radius: 2.5, flags: 3 },
+    ];
+    let refs = choices.iter().map(|c| c.encode(&mut e)).collect::<Vec<_>>();
+    for (choice, &r) in choices.iter().zip(&refs) {
+        assert_eq!(&Choice::decode(e.buf(), r).unwrap(), choice);
+    }
+    // The tag comes first, in order of the variants.
+    let tags = refs.iter().map(|r| e.buf()[r.offset() as usize]).collect::<Vec<_>>();
+    assert_eq!(tags, vec![0, 1, 2, 3]);
+}
@@ -0,0 +1,84 @@
+use crate::encoder::{Decode, DecodeError, Encode, Encoder, Viewable};
+
+piet_gpu! {
+    #[rust_encode]
+    mod tags {
+        struct Dot {
+            kind: u8,
+            r: u16,
+            x: f32,
+        }
+        struct Wide {
+            x: f32,
+        }
+        #[tag(u8)]
+        enum Mark {
+            Blank = 3,
+            Dot(Dot),
+            Wide(Wide),
+            Pair { a: u8, b: bool },
+            Far = 200,
+        }
+        #[tag(u16)]
+        enum Code {
+            A = 10,
+            B { bits: u12, c: bool },
+            C(u16),
+        }
+    }
+}
+
+use self::tags::*;
+
+#[test]
+fn layout() {
+    check_layout_tags().unwrap();
+}
+
+#[test]
+fn discriminants() {
+    assert_eq!(Mark::BLANK_TAG, 3);
+    assert_eq!((Mark::DOT_TAG, Mark::WIDE_TAG, Mark::PAIR_TAG), (4, 5, 6));
+    assert_eq!(Mark::FAR_TAG, 200);
+    assert_eq!((Code::A_TAG, Code::B_TAG, Code::C_TAG), (10, 11, 12));
+}
+
+#[test]
+fn narrow_tags() {
+    let mut e = Encoder::new();
+    let marks = [
+        Mark::Blank,
+        Mark::Dot(Dot { kind: 7, r: 300, x: 1.5 }),
+        Mark::Wide(Wide { x: 2.5 }),
+        Mark::Pair { a: 9, b: true },
+        Mark::Far,
+    ];
+    let refs = marks.iter().map(|m| m.encode(&mut e)).collect::<Vec<_>>();
+    let codes = [Code::A, Code::B { bits: 4000, c: true }, Code::C(65535)];
+    let code_refs = codes.iter().map(|c| c.encode(&mut e)).collect::<Vec<_>>();
+    for (mark, &r) in marks.iter().zip(&refs) {
+        assert_eq!(&Mark::decode(e.buf(), r).unwrap(), mark);
+    }
+    for (code, &r) in codes.iter().zip(&code_refs) {
+        assert_eq!(&Code::decode(e.buf(), r).unwrap(), code);
+    }
+    // The u8 tag shares its word with `kind` and `r`; `x` doesn't fit.
+    let offset = refs[1].offset() as usize;
+    assert_eq!(&e.buf()[offset..offset + 8], &[4, 7, 44, 1, 0, 0, 0xc0, 0x3f]);
+    let offset = code_refs[1].offset() as usize;
+    assert_eq!(&e.buf()[offset..offset + 4], &[11, 0, 0xa0, 0x1f]);
+    assert_eq!(Mark::view(e.buf(), refs[4]).tag(), 200);
+    assert_eq!(Code::view(e.buf(), code_refs[2]).tag(), 12);
+}
+
+#[test]
+fn unknown_tags() {
+    let mut buf = vec![0; Mark::fixed_size()];
+    buf[0] = 99;
+    let err = Mark::decode_from(&buf).unwrap_err();
+    assert_eq!(err, DecodeError::UnknownTag { ty: "Mark", tag: 99 });
+    assert_eq!(err.to_string(), "unknown Mark tag 99");
+    // The word shared with a narrow tag doesn't count towards it.
+    let buf = [13, 0, 0xa0, 0x1f];
+    assert_eq!(Code::decode_from(&buf), Err(DecodeError::UnknownTag { ty: "Code", tag: 13 }));
+}
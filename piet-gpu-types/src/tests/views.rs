@@ -0,0 +1,74 @@
+use crate::encoder::{Encode, Encoder, Viewable};
+
+use super::roundtrip::roundtrip::*;
+
+#[test]
+fn struct_views() {
+    let mut e = Encoder::new();
+    let inner = Inner { x: 7, y: [9, 65000] };
+    let r = inner.encode(&mut e);
+    let mixed = Mixed {
+        a: 1.5,
+        b: -3,
+        c: [2.0, -4.0],
+        d: [1, 2, 3, 4],
+        e: 513,
+        f: [-1, 2, -3],
+        g: r,
+        i: inner,
+    };
+    let mixed = mixed.encode(&mut e);
+    let view = Mixed::view(e.buf(), mixed);
+    assert_eq!((view.a(), view.b(), view.c()), (1.5, -3, [2.0, -4.0]));
+    // Packed fields are read whole, as in the shader, and unpacked.
+    assert_eq!(MixedView::unpack_d(view.d()), [1, 2, 3, 4]);
+    assert_eq!(MixedView::unpack_e(view.e()), 513);
+    assert_eq!(view.f(), [-1, 2, -3]);
+    assert_eq!(view.g(), r);
+    assert_eq!(Inner::view(e.buf(), view.g()).x(), 7);
+    assert_eq!(InnerView::unpack_y(view.i().y()), [9, 65000]);
+}
+
+#[test]
+fn slice_and_array_views() {
+    let mut e = Encoder::new();
+    let stops = e.encode_slice(&[0.0f32, 0.5, 1.0]);
+    let inners = e.encode_slice(&[Inner { x: 1, y: [2, 3] }]);
+    let deltas = e.encode_slice(&[-1i8, 2, -128]);
+    let ramp = Ramp { id: 3, stops, inners, deltas }.encode(&mut e);
+    let view = Ramp::view(e.buf(), ramp);
+    assert_eq!((view.id(), view.stops_len(), view.stops_index(1)), (3, 3, 0.5));
+    assert_eq!(view.deltas_index(2), -128);
+    assert_eq!(Inner::view(e.buf(), view.inners_index(0)).x(), 1);
+    let quad = Quad {
+        head: 5,
+        corners: [[1.0, 2.0], [3.0, 4.0], [5.0, 6.0], [7.0, 8.0]],
+        boxes: [Inner { x: 1, y: [2, 3] }, Inner { x: 4, y: [5, 6] }],
+        tail: 9,
+    };
+    let quad = quad.encode(&mut e);
+    let view = Quad::view(e.buf(), quad);
+    assert_eq!((QuadView::unpack_head(view.head()), QuadView::unpack_tail(view.tail())), (5, 9));
+    assert_eq!((view.corners(3), view.boxes(1).x()), ([7.0, 8.0], 4));
+}
+
+#[test]
+fn enum_views() {
+    let mut e = Encoder::new();
+    let inner = Inner { x: 7, y: [9, 65000] };
+    let first = Choice::First(inner.clone()).encode(&mut e);
+    let line = Choice::Line(inner.clone(), Inner { x: 1, y: [2, 3] }, 77).encode(&mut e);
+    let rect = Choice::Rect { bbox: inner, radius: 2.5, flags: 3 }.encode(&mut e);
+    let nothing = Choice::Nothing.encode(&mut e);
+    let view = Choice::view(e.buf(), first);
+    assert_eq!(view.tag(), Choice::FIRST_TAG);
+    assert_eq!(view.as_first().unwrap().x(), 7);
+    assert!(view.as_rect().is_none());
+    let line = Choice::view(e.buf(), line).as_line().unwrap();
+    assert_eq!((line.f0().x(), line.f1().x()), (7, 1));
+    assert_eq!(ChoiceLineView::unpack_f2(line.f2()), 77);
+    let rect = Choice::view(e.buf(), rect).as_rect().unwrap();
+    assert_eq!((rect.bbox().x(), rect.radius()), (7, 2.5));
+    assert_eq!(ChoiceRectView::unpack_flags(rect.flags()), 3);
+    assert_eq!(Choice::view(e.buf(), nothing).tag(), Choice::NOTHING_TAG);
+}
@@ -46,15 +46,16 @@ fn convert_rect_to_vertices(rc: Rect) -> [Vertex; 4] {
 
 const CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.0];
 
+/// The scene readers, generated by the build script.
+const READERS: &str = include_str!(concat!(env!("OUT_DIR"), "/readers.hlsl"));
+
 fn materialize_per_tile_command_list_kernel_code(
     ptcl_num_tiles_per_tg_x: u32,
     ptcl_num_tiles_per_tg_y: u32,
-    readers_path: &Path,
     utils_path: &Path,
     shader_template_path: &Path,
     shader_path: &Path,
 ) {
-    let readers = std::fs::read_to_string(readers_path).expect("could not read data from provided readers.hlsl path");
     let utils = std::fs::read_to_string(utils_path).expect("could not read data from provided utils.hlsl");
     
     let step0 = std::fs::read_to_string(shader_template_path)
@@ -62,7 +63,7 @@ fn materialize_per_tile_command_list_kernel_code(
 
     let step1 = step0.replace("~PTCL_X~", &format!("{}", ptcl_num_tiles_per_tg_x));
     let step2 = step1.replace("~PTCL_Y~", &format!("{}", ptcl_num_tiles_per_tg_y));
-    let step3 = step2.replace("~READERS~", &readers);
+    let step3 = step2.replace("~READERS~", READERS);
     let step4 = step3.replace("~UTILS~", &utils);
     
     std::fs::write(shader_path, step4).expect("could not write to provided shader path");
@@ -71,19 +72,17 @@ fn materialize_per_tile_command_list_kernel_code(
 fn materialize_paint_kernel_code(
     paint_num_pixels_per_tg_x: u32,
     paint_num_pixels_per_tg_y: u32,
-    reader_path: &Path,
     utils_path: &Path,
     shader_template_path: &Path,
     shader_path: &Path,
 ) {
-    let reader = std::fs::read_to_string(reader_path).expect("could not read data from provided readers.hlsl path");
     let utils = std::fs::read_to_string(utils_path).expect("could not read data from provided utils.hlsl");
 
     let step0 = std::fs::read_to_string(shader_template_path)
         .expect("could not write to provided shader path");
     let step1 = step0.replace("~P_X~", &format!("{}", paint_num_pixels_per_tg_x));
     let step2 = step1.replace("~P_Y~", &format!("{}", paint_num_pixels_per_tg_y));
-    let step3 = step2.replace("~READERS~", &reader);
+    let step3 = step2.replace("~READERS~", READERS);
     let step4 = step3.replace("~UTILS~", &utils);
     
     std::fs::write(shader_path, step4).expect("shader template could not be materialized");
@@ -392,7 +391,6 @@ impl GpuState {
         };
 
         let shader_folder = Path::new("shaders");
-        let readers_path = shader_folder.join(Path::new("readers.hlsl"));
         let utils_path = shader_folder.join(Path::new("utils.hlsl"));
 
         let ptcl_kernel_template_path = shader_folder.join(Path::new("ptcl_kernel_template.hlsl"));
@@ -400,7 +398,6 @@ impl GpuState {
         materialize_per_tile_command_list_kernel_code(
             per_tile_command_lists_num_tiles_per_tg_x,
             per_tile_command_lists_num_tiles_per_tg_y,
-            &readers_path,
             &utils_path,
             &ptcl_kernel_template_path,
             &ptcl_kernel_path,
@@ -412,7 +409,6 @@ impl GpuState {
         materialize_paint_kernel_code(
             paint_num_pixels_per_tg_x,
             paint_num_pixels_per_tg_y,
-            &readers_path,
             &utils_path,
             &paint_kernel_template_path,
             &paint_kernel_path,
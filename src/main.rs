@@ -34,9 +34,9 @@ use std::collections::HashSet;
 use std::convert::TryFrom;
 use std::fs::File;
 use std::hash::Hash;
-use std::io::{Read, Write};
+use std::io::Read;
 use std::os::windows::ffi::OsStrExt;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
 #[derive(Clone)]
@@ -569,15 +569,6 @@ fn populate_render_context(
 //}
 
 fn main() {
-    {
-        let gpu_side_code: String = piet_gpu_types::scene::gen_gpu_scene("HLSL");
-        let shader_folder = Path::new("shaders");
-        let readers_fp = shader_folder.join(Path::new("readers.hlsl"));
-        let mut f = File::create(readers_fp).unwrap();
-        f.write_all(gpu_side_code.as_bytes()).unwrap();
-        f.sync_all().unwrap();
-    }
-
     unsafe {
         println!("creating window...");
         let wnd = window::Window::new(win32_string("test"), win32_string("test"));